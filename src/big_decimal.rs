@@ -4,12 +4,18 @@ use near_sdk::json_types::U128;
 use near_sdk::serde::{Deserialize, Serialize, Serializer};
 use std::cmp::{max_by, min_by, Ordering};
 use std::fmt::{Display, Formatter};
-use std::ops::{Add, Div, Mul, Sub};
+use std::ops::{Add, Div, Mul, Rem, Sub};
 use std::str::FromStr;
 
-uint::construct_uint!(
-    pub struct U384(6);
-);
+mod u384 {
+    #![allow(clippy::assign_op_pattern, clippy::manual_range_contains)]
+
+    uint::construct_uint!(
+        pub struct U384(6);
+    );
+}
+
+pub use u384::U384;
 
 pub type WBigDecimal = U128;
 pub type WBalance = U128;
@@ -67,7 +73,7 @@ impl FromStr for BigDecimal {
         } else {
             (s, 0u128)
         };
-        let int = U384::from(int.parse::<u128>().unwrap());
+        let int = U384::from(int.parse::<u128>().map_err(|_| PARSE_INT_ERROR)?);
         if dec >= BIG_DIVISOR {
             return Err(String::from("The decimal part is too large"));
         }
@@ -162,6 +168,14 @@ impl Div for BigDecimal {
     }
 }
 
+impl Rem for BigDecimal {
+    type Output = Self;
+
+    fn rem(self, rhs: Self) -> Self::Output {
+        Self(self.0 % rhs.0)
+    }
+}
+
 impl From<LowU128> for BigDecimal {
     fn from(low_u128: LowU128) -> Self {
         Self(U384::from(low_u128.0))
@@ -183,10 +197,19 @@ impl BigDecimal {
         Self((self.0 * U384::from(ratio) + U384::from(MAX_RATIO / 2)) / U384::from(MAX_RATIO))
     }
 
+    /// Like `mul_ratio`, but rounds up.
+    pub fn mul_ratio_ceil(&self, ratio: u32) -> Self {
+        Self((self.0 * U384::from(ratio) + U384::from(MAX_RATIO - 1)) / U384::from(MAX_RATIO))
+    }
+
     pub fn div_ratio(&self, ratio: u32) -> Self {
         Self((self.0 * U384::from(MAX_RATIO) + U384::from(MAX_RATIO / 2)) / U384::from(ratio))
     }
 
+    pub fn floor_u128(&self) -> u128 {
+        (self.0 / U384::from(BIG_DIVISOR)).as_u128()
+    }
+
     pub fn round_u128(&self) -> u128 {
         ((self.0 + U384::from(HALF_DIVISOR)) / U384::from(BIG_DIVISOR)).as_u128()
     }
@@ -206,6 +229,15 @@ impl BigDecimal {
         Self(self.0 / U384::from(rhs))
     }
 
+    /// The greatest multiple of `step` that is not greater than `self`.
+    pub fn floor_to_step(&self, step: &Self) -> Self {
+        *self - *self % *step
+    }
+
+    pub fn is_multiple_of(&self, step: &Self) -> bool {
+        *self % *step == Self::zero()
+    }
+
     pub fn zero() -> Self {
         Self(U384::zero())
     }
@@ -240,7 +272,7 @@ impl PartialEq<Self> for BigDecimal {
 
 impl PartialOrd for BigDecimal {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.0.partial_cmp(&other.0)
+        Some(self.cmp(other))
     }
 }
 
@@ -316,6 +348,42 @@ mod test {
         assert_eq!(ten, BigDecimal::from(10u128));
     }
 
+    #[test]
+    fn should_floor() {
+        assert_eq!(BigDecimal::from_str("2.9").unwrap().floor_u128(), 2);
+        assert_eq!(BigDecimal::from_str("2.9").unwrap().round_u128(), 3);
+        assert_eq!(BigDecimal::from(7u128).floor_u128(), 7);
+    }
+
+    #[test]
+    fn should_mul_ratio_ceil() {
+        let raw = |value: u128| BigDecimal::from(LowU128::from(value));
+        assert_eq!(raw(15).mul_ratio(3000), raw(5));
+        assert_eq!(raw(15).mul_ratio_ceil(3000), raw(5));
+        assert_eq!(raw(15).mul_ratio(100), BigDecimal::zero());
+        assert_eq!(raw(15).mul_ratio_ceil(100), raw(1));
+    }
+
+    #[test]
+    fn should_floor_to_step() {
+        let step = BigDecimal::from_str("0.05").unwrap();
+        let value = BigDecimal::from_str("1.234").unwrap();
+        assert_eq!(value % step, BigDecimal::from_str("0.034").unwrap());
+        assert_eq!(
+            value.floor_to_step(&step),
+            BigDecimal::from_str("1.2").unwrap()
+        );
+        assert!(BigDecimal::from_str("1.25").unwrap().is_multiple_of(&step));
+        assert!(!value.is_multiple_of(&step));
+    }
+
+    #[test]
+    fn should_not_parse_invalid_int() {
+        assert!(BigDecimal::from_str("ten").is_err());
+        assert!(BigDecimal::from_str("-1.5").is_err());
+        assert!(BigDecimal::from_str("").is_err());
+    }
+
     #[test]
     fn should_be_0_0000000628() {
         assert_eq!(
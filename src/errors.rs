@@ -0,0 +1,260 @@
+use crate::*;
+use std::fmt;
+use std::str::FromStr;
+
+/// Why an order is rejected.
+/// The entry points panic with `"<code>: <details>"`, the codes are stable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OrderError {
+    /// The order is not a JSON of `Order`
+    InvalidJson(String),
+    /// "amount" or "price" is not a non-negative decimal number
+    InvalidAmount(String),
+    /// "amount" or "price" is zero
+    ZeroAmount,
+    /// "sell_token" and "buy_token" are the same token
+    SameTokens,
+    /// No order book is allowed for the tokens
+    UnknownPair(PairId),
+    /// The pair is registered, but doesn't accept new orders
+    PairDisabled(PairId),
+    /// "account_id" of the order isn't the account that submits it
+    AccountMismatch(AccountId),
+    /// The price is not a multiple of the tick size of the pair
+    InvalidTick(BigDecimal),
+    /// The amount is less than the lot size of the pair
+    BelowLotSize(BigDecimal),
+    /// The amount times the price is less than the minimum notional of the pair
+    BelowMinNotional(BigDecimal),
+    /// The contract is paused and doesn't accept new orders
+    Paused,
+    /// A fill-or-kill order can't be filled completely by the book
+    NotFillable,
+    /// A post-only order would be filled at once
+    WouldTakeLiquidity,
+    /// "expires_at" is not after the current block timestamp
+    Expired,
+    /// A market order has neither "min_receive" nor "max_price",
+    /// or "max_price" is given for a sell order
+    InvalidGuard,
+    /// The fills of a market order would breach its "min_receive" or "max_price"
+    SlippageExceeded,
+    /// The tokens transferred with the order are not its "sell_token"
+    WrongToken(AccountId),
+    /// The tokens transferred with the order don't cover its sell amount
+    DepositTooSmall(BigDecimal),
+    /// The available balance doesn't cover the sell amount of the order
+    InsufficientBalance(AccountId),
+}
+
+impl OrderError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            OrderError::InvalidJson(_) => "E001_INVALID_JSON",
+            OrderError::InvalidAmount(_) => "E002_INVALID_AMOUNT",
+            OrderError::ZeroAmount => "E003_ZERO_AMOUNT",
+            OrderError::SameTokens => "E004_SAME_TOKENS",
+            OrderError::UnknownPair(_) => "E005_UNKNOWN_PAIR",
+            OrderError::AccountMismatch(_) => "E006_ACCOUNT_MISMATCH",
+            OrderError::PairDisabled(_) => "E007_PAIR_DISABLED",
+            OrderError::InvalidTick(_) => "E008_INVALID_TICK",
+            OrderError::BelowLotSize(_) => "E009_BELOW_LOT_SIZE",
+            OrderError::BelowMinNotional(_) => "E010_BELOW_MIN_NOTIONAL",
+            OrderError::Paused => "E011_PAUSED",
+            OrderError::NotFillable => "E012_NOT_FILLABLE",
+            OrderError::WouldTakeLiquidity => "E013_WOULD_TAKE_LIQUIDITY",
+            OrderError::Expired => "E014_EXPIRED",
+            OrderError::InvalidGuard => "E015_INVALID_GUARD",
+            OrderError::SlippageExceeded => "E016_SLIPPAGE_EXCEEDED",
+            OrderError::WrongToken(_) => "E017_WRONG_TOKEN",
+            OrderError::DepositTooSmall(_) => "E018_DEPOSIT_TOO_SMALL",
+            OrderError::InsufficientBalance(_) => "E019_INSUFFICIENT_BALANCE",
+        }
+    }
+}
+
+impl fmt::Display for OrderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderError::InvalidJson(error) => write!(f, "{}: {}", self.code(), error),
+            OrderError::InvalidAmount(value) => {
+                write!(f, "{}: {} is not a valid amount", self.code(), value)
+            }
+            OrderError::ZeroAmount => write!(f, "{}: the amount is zero", self.code()),
+            OrderError::SameTokens => {
+                write!(f, "{}: the sell and buy tokens are the same", self.code())
+            }
+            OrderError::UnknownPair((base, quote)) => {
+                write!(f, "{}: the pair {}|{} is unknown", self.code(), base, quote)
+            }
+            OrderError::PairDisabled((base, quote)) => write!(
+                f,
+                "{}: the pair {}|{} is disabled",
+                self.code(),
+                base,
+                quote
+            ),
+            OrderError::AccountMismatch(account_id) => write!(
+                f,
+                "{}: the order can't be submitted for {}",
+                self.code(),
+                account_id
+            ),
+            OrderError::InvalidTick(tick_size) => write!(
+                f,
+                "{}: the price must be a multiple of {}",
+                self.code(),
+                tick_size
+            ),
+            OrderError::BelowLotSize(lot_size) => write!(
+                f,
+                "{}: the amount must be at least {}",
+                self.code(),
+                lot_size
+            ),
+            OrderError::BelowMinNotional(min_notional) => write!(
+                f,
+                "{}: the order must be worth at least {} of the quote token",
+                self.code(),
+                min_notional
+            ),
+            OrderError::Paused => write!(f, "{}: the contract is paused", self.code()),
+            OrderError::NotFillable => {
+                write!(f, "{}: the order can't be filled completely", self.code())
+            }
+            OrderError::WouldTakeLiquidity => write!(
+                f,
+                "{}: a post-only order can't be filled at once",
+                self.code()
+            ),
+            OrderError::Expired => write!(f, "{}: the order has already expired", self.code()),
+            OrderError::InvalidGuard => write!(
+                f,
+                "{}: a market order needs min_receive, or max_price for a buy",
+                self.code()
+            ),
+            OrderError::SlippageExceeded => write!(
+                f,
+                "{}: the fills would breach the slippage guard",
+                self.code()
+            ),
+            OrderError::WrongToken(token_id) => write!(
+                f,
+                "{}: {} is not the sell token of the order",
+                self.code(),
+                token_id
+            ),
+            OrderError::DepositTooSmall(required) => write!(
+                f,
+                "{}: the transferred amount doesn't cover the order amount {}",
+                self.code(),
+                required
+            ),
+            OrderError::InsufficientBalance(token_id) => write!(
+                f,
+                "{}: not enough available balance of {}",
+                self.code(),
+                token_id
+            ),
+        }
+    }
+}
+
+impl Order {
+    /// Parses the order and checks the amounts and the tokens.
+    /// The amounts are checked before the whole order, so a bad number
+    /// isn't reported as a bad JSON.
+    pub fn from_json(json: &str) -> Result<Self, OrderError> {
+        let value: serde_json::Value =
+            serde_json::from_str(json).map_err(|err| OrderError::InvalidJson(err.to_string()))?;
+        for field in ["amount", "price"] {
+            if let Some(amount) = value.get(field).and_then(|amount| amount.as_str()) {
+                BigDecimal::from_str(amount)
+                    .map_err(|_| OrderError::InvalidAmount(amount.to_string()))?;
+            }
+        }
+
+        let order: Order = serde_json::from_value(value)
+            .map_err(|err| OrderError::InvalidJson(err.to_string()))?;
+        order.validate()?;
+        Ok(order)
+    }
+
+    pub fn validate(&self) -> Result<(), OrderError> {
+        if self.amount == BigDecimal::zero() || self.price == BigDecimal::zero() {
+            return Err(OrderError::ZeroAmount);
+        }
+        if self.sell_token == self.buy_token {
+            return Err(OrderError::SameTokens);
+        }
+        Ok(())
+    }
+
+    /// The owner in the order must be the account that submits it.
+    pub fn validate_owner(&self, account_id: &AccountId) -> Result<(), OrderError> {
+        if &self.account_id != account_id {
+            return Err(OrderError::AccountMismatch(self.account_id.clone()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(amount: &str, sell_token: &str) -> String {
+        format!("{{\"account_id\":\"alice.near\",\"amount\":\"{}\",\"sell_token\":\"{}\",\"buy_token\":\"usdt.near\",\"price\":\"1\",\"side\":\"sell\"}}", amount, sell_token)
+    }
+
+    #[test]
+    fn test_order_from_json() {
+        assert!(Order::from_json(&order("10", "wnear.near")).is_ok());
+
+        assert!(matches!(
+            Order::from_json("{\"account_id\":"),
+            Err(OrderError::InvalidJson(_))
+        ));
+        assert!(matches!(
+            Order::from_json("{\"account_id\":\"alice.near\"}"),
+            Err(OrderError::InvalidJson(_))
+        ));
+        assert_eq!(
+            Order::from_json(&order("ten", "wnear.near")),
+            Err(OrderError::InvalidAmount("ten".to_string()))
+        );
+        assert_eq!(
+            Order::from_json(&order("-1", "wnear.near")),
+            Err(OrderError::InvalidAmount("-1".to_string()))
+        );
+        assert_eq!(
+            Order::from_json(&order("0.0", "wnear.near")),
+            Err(OrderError::ZeroAmount)
+        );
+        assert_eq!(
+            Order::from_json(&order("10", "usdt.near")),
+            Err(OrderError::SameTokens)
+        );
+    }
+
+    #[test]
+    fn test_validate_owner() {
+        let order = Order::from_json(&order("10", "wnear.near")).unwrap();
+        assert!(order.validate_owner(&"alice.near".parse().unwrap()).is_ok());
+        assert_eq!(
+            order.validate_owner(&"bob.near".parse().unwrap()),
+            Err(OrderError::AccountMismatch("alice.near".parse().unwrap()))
+        );
+    }
+
+    #[test]
+    fn test_error_codes() {
+        assert_eq!(
+            OrderError::ZeroAmount.to_string(),
+            "E003_ZERO_AMOUNT: the amount is zero"
+        );
+        assert!(OrderError::InvalidJson("EOF".to_string())
+            .to_string()
+            .starts_with("E001_INVALID_JSON: "));
+    }
+}
@@ -0,0 +1,187 @@
+//! NEP-297 events of the order lifecycle, logged as `EVENT_JSON:{...}`.
+use crate::matching::Fill;
+use crate::*;
+
+pub const EVENT_STANDARD: &str = "order_book";
+pub const EVENT_STANDARD_VERSION: &str = "1.0.0";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EventLog {
+    pub standard: String,
+    pub version: String,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data")]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    OrderCreated(Vec<OrderEvent>),
+    OrderCancelled(Vec<OrderEvent>),
+    OrderExpired(Vec<OrderEvent>),
+    OrderFilled(Vec<OrderFillEvent>),
+    OrderPartiallyFilled(Vec<OrderFillEvent>),
+    Trade(Vec<Trade>),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OrderEvent {
+    pub order_id: U64,
+    pub account_id: AccountId,
+    /// (base, quote) of the book
+    pub pair_id: PairId,
+    pub side: Side,
+    pub price: BigDecimal,
+    /// Amount of the base token: the whole order when created,
+    /// the rest when cancelled or expired
+    pub amount: BigDecimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OrderFillEvent {
+    pub order_id: U64,
+    pub account_id: AccountId,
+    /// (base, quote) of the book
+    pub pair_id: PairId,
+    pub side: Side,
+    /// The maker's price
+    pub price: BigDecimal,
+    /// Amount of the base token filled
+    pub amount: BigDecimal,
+    /// Amount of the base token left in the order
+    pub remaining: BigDecimal,
+}
+
+impl OrderEvent {
+    pub fn new(order_id: u64, order: &Order) -> Self {
+        Self {
+            order_id: order_id.into(),
+            account_id: order.account_id.clone(),
+            pair_id: order.book_pair_id(),
+            side: order.side,
+            price: order.price,
+            amount: order.amount,
+        }
+    }
+}
+
+impl OrderFillEvent {
+    /// `order` is taken as it was before the fill.
+    pub fn new(order_id: u64, order: &Order, fill: &Fill) -> Self {
+        Self {
+            order_id: order_id.into(),
+            account_id: order.account_id.clone(),
+            pair_id: order.book_pair_id(),
+            side: order.side,
+            price: fill.price,
+            amount: fill.amount,
+            remaining: order.amount - fill.amount,
+        }
+    }
+
+    /// "order_filled" when nothing is left, "order_partially_filled" otherwise.
+    pub fn into_event(self) -> Event {
+        if self.remaining == BigDecimal::zero() {
+            Event::OrderFilled(vec![self])
+        } else {
+            Event::OrderPartiallyFilled(vec![self])
+        }
+    }
+}
+
+impl Event {
+    pub fn emit(self) {
+        let log = EventLog {
+            standard: EVENT_STANDARD.to_string(),
+            version: EVENT_STANDARD_VERSION.to_string(),
+            event: self,
+        };
+        env::log_str(&format!(
+            "EVENT_JSON:{}",
+            serde_json::to_string(&log).unwrap()
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{add_order, set_predecessor};
+    use near_sdk::test_utils::get_logs;
+    use near_sdk::test_utils::test_env::{alice, bob};
+
+    fn events() -> Vec<EventLog> {
+        get_logs()
+            .iter()
+            .filter_map(|log| log.strip_prefix("EVENT_JSON:"))
+            .map(|json| serde_json::from_str(json).unwrap())
+            .collect()
+    }
+
+    fn event_names() -> Vec<String> {
+        get_logs()
+            .iter()
+            .filter_map(|log| log.strip_prefix("EVENT_JSON:"))
+            .map(|json| {
+                let value: serde_json::Value = serde_json::from_str(json).unwrap();
+                value["event"].as_str().unwrap().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_order_created_and_cancelled() {
+        let mut contract = Contract::new();
+
+        let order_id = add_order(&mut contract, alice(), "sell", "2.5", "10");
+        let log = &events()[0];
+        assert_eq!(log.standard, EVENT_STANDARD);
+        assert_eq!(log.version, EVENT_STANDARD_VERSION);
+        match &log.event {
+            Event::OrderCreated(data) => {
+                assert_eq!(data[0].order_id, order_id);
+                assert_eq!(data[0].account_id, alice());
+                assert_eq!(data[0].price.to_string(), "2.5");
+                assert_eq!(data[0].amount.to_string(), "10.0");
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+
+        set_predecessor(alice());
+        contract.cancel_order(order_id);
+        assert_eq!(event_names(), vec!["order_cancelled"]);
+    }
+
+    #[test]
+    fn test_fill_events() {
+        let mut contract = Contract::new();
+
+        let maker_id = add_order(&mut contract, alice(), "sell", "2", "10");
+        let taker_id = add_order(&mut contract, bob(), "buy", "2", "4");
+        assert_eq!(
+            event_names(),
+            vec![
+                "order_created",
+                "trade",
+                "order_partially_filled",
+                "order_filled"
+            ]
+        );
+
+        let logs = events();
+        match (&logs[2].event, &logs[3].event) {
+            (Event::OrderPartiallyFilled(maker), Event::OrderFilled(taker)) => {
+                assert_eq!(maker[0].order_id, maker_id);
+                assert_eq!(maker[0].remaining.to_string(), "6.0");
+                assert_eq!(taker[0].order_id, taker_id);
+                assert_eq!(taker[0].amount.to_string(), "4.0");
+            }
+            events => panic!("Unexpected events {:?}", events),
+        }
+    }
+}
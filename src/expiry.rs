@@ -0,0 +1,160 @@
+use crate::big_decimal::MAX_RATIO;
+use crate::events::{Event, OrderEvent};
+use crate::*;
+use near_sdk::Promise;
+
+/// Part of the storage released by a pruned order that goes to the caller, in bps.
+/// It is taken from the storage deposit of the order's owner.
+pub const PRUNE_BOUNTY_BPS: u32 = 1_000;
+
+#[near_bindgen]
+impl Contract {
+    /// Moves the expired orders among `limit` orders of the (base, quote) pair
+    /// to the history of their owners and unlocks their tokens. Anyone can call it.
+    /// The orders are read by id from `from_order_id`, an open order of the pair,
+    /// or from the first one, and the later ones are not read.
+    /// The caller gets `PRUNE_BOUNTY_BPS` of the storage released by the orders.
+    /// Returns the number of pruned orders.
+    pub fn prune_expired(
+        &mut self,
+        pair_id: PairId,
+        limit: u32,
+        from_order_id: Option<U64>,
+    ) -> u32 {
+        let timestamp = env::block_timestamp();
+        let expired: Vec<(u64, Order)> = match self.ref_orders.get(&pair_id) {
+            Some(orders) => orders
+                .iter_from(from_order_id.map(|order_id| order_id.0))
+                .take(limit as usize)
+                .filter(|(_, order)| order.is_expired(timestamp))
+                .collect(),
+            None => return 0,
+        };
+
+        let mut bounty = 0;
+        for (order_id, order) in &expired {
+            let account_id = &order.account_id;
+            let initial_storage_usage = env::storage_usage();
+            let mut order = self.internal_remove_order(account_id, &pair_id, *order_id);
+            self.internal_unlock(account_id, &order.sell_token, order.sell_amount());
+            order.status = OrderStatus::Expired;
+            self.internal_record_closed_order(account_id, *order_id, &order);
+            self.internal_update_storage(account_id, initial_storage_usage);
+            Event::OrderExpired(vec![OrderEvent::new(*order_id, &order)]).emit();
+
+            let released = initial_storage_usage.saturating_sub(env::storage_usage()) as u128
+                * env::storage_byte_cost();
+            bounty += self.internal_take_storage_deposit(
+                account_id,
+                released * PRUNE_BOUNTY_BPS as u128 / MAX_RATIO as u128,
+            );
+        }
+
+        if bounty > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(bounty);
+        }
+        expired.len() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{
+        add_order, big, order_json, register, register_pair, set_predecessor, wnear_usdt,
+    };
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::test_env::{alice, bob, carol};
+    use near_sdk::test_utils::{get_logs, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    fn set_timestamp(account_id: AccountId, timestamp: u64) {
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(account_id)
+            .block_timestamp(timestamp)
+            .build());
+    }
+
+    /// Alice's sell order of 10 at 2, which expires at `expires_at`.
+    fn add_expiring_order(contract: &mut Contract, expires_at: u64) -> u64 {
+        let mut order: serde_json::Value =
+            serde_json::from_str(&order_json(&alice(), "sell", "2", "10")).unwrap();
+        order["expires_at"] = expires_at.to_string().into();
+
+        register_pair(contract, "wnear.near", "usdt.near");
+        register(contract, alice());
+        contract.internal_deposit(&alice(), &"wnear.near".parse().unwrap(), big("10"));
+        set_predecessor(alice());
+        contract.add_order_from_string(order.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_expired_orders_never_match() {
+        let mut contract = Contract::new();
+        let order_id = add_expiring_order(&mut contract, 100);
+
+        register(&mut contract, bob());
+        contract.internal_deposit(&bob(), &"usdt.near".parse().unwrap(), big("20"));
+        set_timestamp(bob(), 100);
+        let taker_id = contract
+            .add_order_from_string(order_json(&bob(), "buy", "2", "10"))
+            .unwrap();
+        assert!(contract
+            .get_trades_by_pair(wnear_usdt(), None, None)
+            .is_empty());
+        assert!(contract.get_order(U64(order_id)).is_some());
+        assert!(contract.get_order(U64(taker_id)).is_some());
+    }
+
+    #[test]
+    fn test_add_expired_order() {
+        let mut contract = Contract::new();
+        register_pair(&mut contract, "wnear.near", "usdt.near");
+
+        let mut order: serde_json::Value =
+            serde_json::from_str(&order_json(&alice(), "sell", "2", "10")).unwrap();
+        order["expires_at"] = "100".into();
+        set_timestamp(alice(), 100);
+        assert_eq!(
+            contract.add_order_from_string(order.to_string()),
+            Err(OrderError::Expired)
+        );
+    }
+
+    #[test]
+    fn test_prune_expired() {
+        let mut contract = Contract::new();
+        let expired_id = add_expiring_order(&mut contract, 100);
+        let open_id = add_order(&mut contract, carol(), "sell", "3", "5");
+        let deposit = contract.storage_balance_of(alice()).unwrap().total.0;
+
+        set_timestamp(bob(), 101);
+        // Only the order of carol is read
+        assert_eq!(contract.prune_expired(wnear_usdt(), 1, Some(open_id)), 0);
+        assert_eq!(contract.prune_expired(wnear_usdt(), 1, None), 1);
+        assert!(get_logs()
+            .iter()
+            .any(|log| log.contains("\"event\":\"order_expired\"")));
+
+        assert!(contract.get_order(U64(expired_id)).is_none());
+        assert!(contract.get_order(open_id).is_some());
+        let history = contract.view_order_history(alice(), None, None);
+        assert_eq!(history.last().unwrap().1.status, OrderStatus::Expired);
+        let balance = contract.get_balance(alice(), "wnear.near".parse().unwrap());
+        assert_eq!(balance.available, big("10"));
+        assert_eq!(balance.locked, BigDecimal::zero());
+
+        // The bounty is paid from Alice's storage deposit
+        assert!(contract.storage_balance_of(alice()).unwrap().total.0 < deposit);
+
+        assert_eq!(contract.prune_expired(wnear_usdt(), 10, None), 0);
+        assert_eq!(
+            contract.prune_expired(
+                ("usdt.near".parse().unwrap(), "dai.near".parse().unwrap()),
+                10,
+                None
+            ),
+            0
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use crate::big_decimal::MAX_RATIO;
+use crate::ledger::{GAS_FOR_FT_TRANSFER, GAS_FOR_RESOLVE_WITHDRAW};
+use crate::*;
+use near_contract_standards::fungible_token::core::ext_ft_core;
+use near_sdk::json_types::U128;
+use near_sdk::{assert_one_yocto, is_promise_success, Promise};
+
+/// Fees in basis points of the tokens an account receives from a fill.
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeSchedule {
+    /// Paid by the resting order
+    pub maker_fee_bps: u32,
+    /// Paid by the incoming order
+    pub taker_fee_bps: u32,
+}
+
+/// The fee of `fee_bps` on `amount`, rounded up and never more than `amount`.
+pub(crate) fn fee_of(amount: BigDecimal, fee_bps: u32) -> BigDecimal {
+    amount.mul_ratio_ceil(fee_bps).min(amount)
+}
+
+impl FeeSchedule {
+    fn assert_valid(&self) {
+        assert!(
+            self.maker_fee_bps <= MAX_RATIO && self.taker_fee_bps <= MAX_RATIO,
+            "A fee can't be more than {} bps",
+            MAX_RATIO
+        );
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Fees of the pairs without their own schedule. Only the owner or an operator.
+    pub fn set_default_fees(&mut self, fees: FeeSchedule) {
+        self.assert_operator();
+        fees.assert_valid();
+        self.default_fees = fees;
+    }
+
+    /// Fees of one pair, `None` goes back to the default fees. Only the owner or an operator.
+    pub fn set_pair_fees(&mut self, pair_id: PairId, fees: Option<FeeSchedule>) {
+        self.assert_operator();
+        if let Some(fees) = &fees {
+            fees.assert_valid();
+        }
+        let mut pair = self.pairs.get(&pair_id).expect("Pair not found");
+        pair.fees = fees;
+        self.pairs.insert(&pair_id, &pair);
+    }
+
+    /// Fees applied to the fills of the pair.
+    pub fn get_fees(&self, pair_id: PairId) -> FeeSchedule {
+        self.pairs
+            .get(&pair_id)
+            .and_then(|pair| pair.fees)
+            .unwrap_or(self.default_fees)
+    }
+
+    /// Collected fees per token.
+    pub fn get_fee_vault(&self) -> Vec<(AccountId, BigDecimal)> {
+        self.fee_vault.to_vec()
+    }
+
+    /// Sends collected fees of `token_id` to the owner. Only the owner.
+    /// The vault is restored if the transfer fails.
+    #[payable]
+    pub fn withdraw_fees(&mut self, token_id: AccountId, amount: U128) -> Promise {
+        assert_one_yocto();
+        self.assert_owner();
+
+        let collected = self.fee_vault.get(&token_id).unwrap_or_default();
+        let value = BigDecimal::from(amount.0);
+        assert!(collected >= value, "Not enough collected fees");
+        self.fee_vault.insert(&token_id, &(collected - value));
+
+        ext_ft_core::ext(token_id.clone())
+            .with_attached_deposit(1)
+            .with_static_gas(GAS_FOR_FT_TRANSFER)
+            .ft_transfer(self.owner_id.clone(), amount, None)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_WITHDRAW)
+                    .resolve_withdraw_fees(token_id, amount),
+            )
+    }
+
+    #[private]
+    pub fn resolve_withdraw_fees(&mut self, token_id: AccountId, amount: U128) {
+        if !is_promise_success() {
+            self.internal_collect_fee(&token_id, BigDecimal::from(amount.0));
+        }
+    }
+}
+
+impl Contract {
+    /// Takes the fee from `amount` of `token_id` an account receives.
+    /// The fee is rounded up, so the protocol never gets less than its share.
+    /// Returns what is left for the account.
+    pub(crate) fn internal_charge_fee(
+        &mut self,
+        token_id: &AccountId,
+        amount: BigDecimal,
+        fee_bps: u32,
+    ) -> BigDecimal {
+        if fee_bps == 0 {
+            return amount;
+        }
+        let fee = fee_of(amount, fee_bps);
+        self.internal_collect_fee(token_id, fee);
+        amount - fee
+    }
+
+    pub(crate) fn internal_collect_fee(&mut self, token_id: &AccountId, amount: BigDecimal) {
+        let collected = self.fee_vault.get(token_id).unwrap_or_default();
+        self.fee_vault.insert(token_id, &(collected + amount));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{add_order, big, new_contract, set_predecessor, usdt, wnear};
+    use near_sdk::test_utils::test_env::{alice, bob, carol};
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::testing_env;
+
+    fn fees(maker_fee_bps: u32, taker_fee_bps: u32) -> FeeSchedule {
+        FeeSchedule {
+            maker_fee_bps,
+            taker_fee_bps,
+        }
+    }
+
+    #[test]
+    fn test_fees_are_collected() {
+        let mut contract = new_contract(carol());
+        set_predecessor(carol());
+        contract.set_default_fees(fees(10, 20));
+
+        // Alice is the maker, Bob is the taker
+        add_order(&mut contract, alice(), "sell", "2", "10");
+        add_order(&mut contract, bob(), "buy", "2", "10");
+
+        assert_eq!(contract.get_balance(bob(), wnear()).available, big("9.98"));
+        assert_eq!(
+            contract.get_balance(alice(), usdt()).available,
+            big("19.98")
+        );
+        assert_eq!(
+            contract.get_fee_vault(),
+            vec![(wnear(), big("0.02")), (usdt(), big("0.02"))]
+        );
+    }
+
+    #[test]
+    fn test_pair_fees_override_default() {
+        let mut contract = new_contract(carol());
+        set_predecessor(carol());
+        contract.set_default_fees(fees(10, 20));
+        contract.set_pair_fees((wnear(), usdt()), Some(fees(0, 50)));
+        assert_eq!(contract.get_fees((wnear(), usdt())), fees(0, 50));
+
+        add_order(&mut contract, alice(), "sell", "2", "10");
+        add_order(&mut contract, bob(), "buy", "2", "10");
+        assert_eq!(contract.get_balance(bob(), wnear()).available, big("9.95"));
+        assert_eq!(contract.get_balance(alice(), usdt()).available, big("20"));
+
+        set_predecessor(carol());
+        contract.set_pair_fees((wnear(), usdt()), None);
+        assert_eq!(contract.get_fees((wnear(), usdt())), fees(10, 20));
+    }
+
+    #[test]
+    fn test_withdraw_fees() {
+        let mut contract = new_contract(carol());
+        contract.internal_collect_fee(&usdt(), big("5"));
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(carol())
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_fees(usdt(), U128(3));
+        assert_eq!(contract.get_fee_vault(), vec![(usdt(), big("2"))]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn test_withdraw_fees_not_owner() {
+        let mut contract = new_contract(carol());
+        contract.internal_collect_fee(&usdt(), big("5"));
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(alice())
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_fees(usdt(), U128(3));
+    }
+
+    #[test]
+    #[should_panic(expected = "A fee can't be more than 10000 bps")]
+    fn test_fee_too_high() {
+        let mut contract = new_contract(carol());
+        set_predecessor(carol());
+        contract.set_default_fees(fees(10, 10001));
+    }
+}
@@ -0,0 +1,229 @@
+use crate::*;
+
+/// Closed orders kept per account until the owner or an operator changes it
+pub const DEFAULT_HISTORY_RETENTION: u64 = 50;
+
+#[derive(
+    BorshDeserialize,
+    BorshSerialize,
+    Serialize,
+    Deserialize,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    /// In the book, nothing filled yet
+    #[default]
+    Open,
+    /// In the book, filled in part
+    PartiallyFilled,
+    /// Filled completely, moved to the history
+    Filled,
+    /// Cancelled by its owner, moved to the history
+    Cancelled,
+    /// Removed after its expiry, moved to the history
+    Expired,
+}
+
+/// Closed orders of one account, the oldest ones are dropped beyond the retention.
+#[derive(BorshDeserialize, BorshSerialize, Debug)]
+pub struct OrderHistory {
+    /// Position of the oldest kept order
+    first: u64,
+    /// Position of the next closed order
+    next: u64,
+    orders: LookupMap<u64, (u64, Order)>,
+}
+
+impl OrderHistory {
+    fn new(account_id: &AccountId) -> Self {
+        Self {
+            first: 0,
+            next: 0,
+            orders: LookupMap::new(StorageKey::AccountHistory {
+                account_hash: env::sha256_array(account_id.as_bytes()),
+            }),
+        }
+    }
+
+    fn len(&self) -> u64 {
+        self.next - self.first
+    }
+
+    fn push(&mut self, order_id: u64, order: &Order) {
+        self.orders.insert(&self.next, &(order_id, order.clone()));
+        self.next += 1;
+    }
+
+    /// Drops the oldest orders until at most `retention` are kept.
+    fn truncate(&mut self, retention: u64) {
+        while self.len() > retention {
+            self.orders.remove(&self.first);
+            self.first += 1;
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Closed orders of the account, the oldest kept one first.
+    pub fn view_order_history(
+        &self,
+        account_id: AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<(U64, Order)> {
+        let history = match self.order_history.get(&account_id) {
+            Some(history) => history,
+            None => return vec![],
+        };
+        let from_index = history.first.saturating_add(from_index.unwrap_or(0));
+        let limit = limit.unwrap_or(DEFAULT_LIMIT);
+        (from_index..std::cmp::min(from_index.saturating_add(limit), history.next))
+            .filter_map(|index| history.orders.get(&index))
+            .map(|(order_id, order)| (order_id.into(), order))
+            .collect()
+    }
+
+    /// How many closed orders are kept per account. Only the owner or an operator.
+    /// A smaller value applies to each account the next time one of its orders closes.
+    pub fn set_history_retention(&mut self, retention: u64) {
+        self.assert_operator();
+        self.history_retention = retention;
+    }
+
+    pub fn get_history_retention(&self) -> u64 {
+        self.history_retention
+    }
+}
+
+impl Contract {
+    /// Adds an order that left the book to the history of its owner.
+    /// The storage is paid by the caller's measurement, like the removal of the order.
+    pub(crate) fn internal_record_closed_order(
+        &mut self,
+        account_id: &AccountId,
+        order_id: u64,
+        order: &Order,
+    ) {
+        let mut history = self
+            .order_history
+            .get(account_id)
+            .unwrap_or_else(|| OrderHistory::new(account_id));
+        history.push(order_id, order);
+        history.truncate(self.history_retention);
+
+        if history.len() == 0 {
+            self.order_history.remove(account_id);
+        } else {
+            self.order_history.insert(account_id, &history);
+        }
+    }
+
+    /// Removes the whole history of the account, e.g. when it unregisters.
+    pub(crate) fn internal_clear_history(&mut self, account_id: &AccountId) {
+        if let Some(mut history) = self.order_history.remove(account_id) {
+            history.truncate(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{add_order, big, set_predecessor};
+    use near_sdk::test_utils::test_env::{alice, bob};
+
+    fn statuses(orders: &[(U64, Order)]) -> Vec<OrderStatus> {
+        orders.iter().map(|(_, order)| order.status).collect()
+    }
+
+    #[test]
+    fn test_order_lifecycle() {
+        let mut contract = Contract::new();
+
+        let maker_id = add_order(&mut contract, alice(), "sell", "2", "10");
+        let maker = contract.get_order(maker_id).unwrap();
+        assert_eq!(maker.status, OrderStatus::Open);
+        assert_eq!(maker.filled_amount, BigDecimal::zero());
+        assert_eq!(maker.created_at, U64(env::block_timestamp()));
+
+        let taker_id = add_order(&mut contract, bob(), "buy", "2", "4");
+        let maker = contract.get_order(maker_id).unwrap();
+        assert_eq!(maker.status, OrderStatus::PartiallyFilled);
+        assert_eq!(maker.filled_amount, big("4"));
+        assert_eq!(maker.amount, big("6"));
+        let partially_filled = Some(OrderFilter {
+            status: Some(OrderStatus::PartiallyFilled),
+            ..Default::default()
+        });
+        assert_eq!(
+            contract
+                .view_orders(alice(), partially_filled, None, None)
+                .len(),
+            1
+        );
+
+        // The taker is filled at once and goes straight to the history
+        assert!(contract.get_order(taker_id).is_none());
+        let history = contract.view_order_history(bob(), None, None);
+        assert_eq!(history[0].0, taker_id);
+        assert_eq!(statuses(&history), [OrderStatus::Filled]);
+
+        set_predecessor(alice());
+        contract.cancel_order(maker_id);
+        let history = contract.view_order_history(alice(), None, None);
+        assert_eq!(statuses(&history), [OrderStatus::Cancelled]);
+        assert_eq!(history[0].1.filled_amount, big("4"));
+    }
+
+    #[test]
+    fn test_history_retention() {
+        let mut contract = Contract::new();
+        set_predecessor(bob());
+        contract.set_history_retention(2);
+
+        let mut order_ids = vec![];
+        for _ in 0..3 {
+            let order_id = add_order(&mut contract, alice(), "sell", "2", "1");
+            set_predecessor(alice());
+            contract.cancel_order(order_id);
+            order_ids.push(order_id);
+        }
+
+        let history = contract.view_order_history(alice(), None, None);
+        assert_eq!(
+            history
+                .iter()
+                .map(|(order_id, _)| *order_id)
+                .collect::<Vec<_>>(),
+            order_ids[1..]
+        );
+        assert_eq!(
+            contract.view_order_history(alice(), Some(1), Some(5))[0].0,
+            order_ids[2]
+        );
+        assert!(contract.view_order_history(bob(), None, None).is_empty());
+
+        set_predecessor(bob());
+        contract.set_history_retention(0);
+        let order_id = add_order(&mut contract, alice(), "sell", "2", "1");
+        set_predecessor(alice());
+        contract.cancel_order(order_id);
+        assert!(contract.view_order_history(alice(), None, None).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner or an operator can call this method")]
+    fn test_set_history_retention_not_operator() {
+        let mut contract = Contract::new();
+        set_predecessor(alice());
+        contract.set_history_retention(2);
+    }
+}
@@ -0,0 +1,313 @@
+use crate::matching::Fill;
+use crate::*;
+use near_contract_standards::fungible_token::core::ext_ft_core;
+use near_sdk::json_types::U128;
+use near_sdk::{assert_one_yocto, is_promise_success, Gas, Promise};
+
+pub(crate) const GAS_FOR_FT_TRANSFER: Gas = Gas(10_000_000_000_000);
+pub(crate) const GAS_FOR_RESOLVE_WITHDRAW: Gas = Gas(5_000_000_000_000);
+
+/// Tokens of one account in the smallest units of the token.
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, Default, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Balance {
+    /// Can be withdrawn or spent by a new order
+    pub available: BigDecimal,
+    /// Reserved by the open orders
+    pub locked: BigDecimal,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Sends `amount` of the available balance of `token_id` to the caller.
+    /// The balance is restored if the transfer fails.
+    #[payable]
+    pub fn withdraw(&mut self, token_id: AccountId, amount: U128) -> Promise {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+
+        let mut balance = self.internal_get_balance(&account_id, &token_id);
+        let value = BigDecimal::from(amount.0);
+        assert!(balance.available >= value, "Not enough available balance");
+        balance.available = balance.available - value;
+        self.internal_set_balance(&account_id, &token_id, &balance);
+
+        ext_ft_core::ext(token_id.clone())
+            .with_attached_deposit(1)
+            .with_static_gas(GAS_FOR_FT_TRANSFER)
+            .ft_transfer(account_id.clone(), amount, None)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_WITHDRAW)
+                    .resolve_withdraw(account_id, token_id, amount),
+            )
+    }
+
+    #[private]
+    pub fn resolve_withdraw(&mut self, account_id: AccountId, token_id: AccountId, amount: U128) {
+        if !is_promise_success() {
+            self.internal_deposit(&account_id, &token_id, BigDecimal::from(amount.0));
+        }
+    }
+
+    pub fn get_balance(&self, account_id: AccountId, token_id: AccountId) -> Balance {
+        self.internal_get_balance(&account_id, &token_id)
+    }
+
+    pub fn get_balances(&self, account_id: AccountId) -> Vec<(AccountId, Balance)> {
+        self.balances
+            .get(&account_id)
+            .map(|balances| balances.to_vec())
+            .unwrap_or_default()
+    }
+}
+
+impl Contract {
+    pub(crate) fn internal_get_balance(
+        &self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+    ) -> Balance {
+        self.balances
+            .get(account_id)
+            .and_then(|balances| balances.get(token_id))
+            .unwrap_or_default()
+    }
+
+    fn internal_set_balance(
+        &mut self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+        balance: &Balance,
+    ) {
+        let mut balances = self.balances.get(account_id).unwrap_or_else(|| {
+            UnorderedMap::new(StorageKey::AccountBalances {
+                account_hash: env::sha256_array(account_id.as_bytes()),
+            })
+        });
+
+        balances.insert(token_id, balance);
+        self.balances.insert(account_id, &balances);
+    }
+
+    pub(crate) fn internal_deposit(
+        &mut self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+        amount: BigDecimal,
+    ) {
+        let mut balance = self.internal_get_balance(account_id, token_id);
+        balance.available = balance.available + amount;
+        self.internal_set_balance(account_id, token_id, &balance);
+    }
+
+    /// Moves `amount` from the available balance to the locked one.
+    pub(crate) fn internal_lock(
+        &mut self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+        amount: BigDecimal,
+    ) -> Result<(), OrderError> {
+        let mut balance = self.internal_get_balance(account_id, token_id);
+        if balance.available < amount {
+            return Err(OrderError::InsufficientBalance(token_id.clone()));
+        }
+        balance.available = balance.available - amount;
+        balance.locked = balance.locked + amount;
+        self.internal_set_balance(account_id, token_id, &balance);
+        Ok(())
+    }
+
+    /// Moves `amount` from the locked balance back to the available one.
+    pub(crate) fn internal_unlock(
+        &mut self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+        amount: BigDecimal,
+    ) {
+        let mut balance = self.internal_get_balance(account_id, token_id);
+        balance.locked = balance.locked - amount;
+        balance.available = balance.available + amount;
+        self.internal_set_balance(account_id, token_id, &balance);
+    }
+
+    fn internal_spend_locked(
+        &mut self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+        amount: BigDecimal,
+    ) {
+        let mut balance = self.internal_get_balance(account_id, token_id);
+        balance.locked = balance.locked - amount;
+        self.internal_set_balance(account_id, token_id, &balance);
+    }
+
+    /// Moves the tokens of one fill between the buyer and the seller of the base token.
+    /// The buyer has locked the quote token at its own price, so the lock is released
+    /// for the filled amount and the difference to the fill price is made available again.
+    /// Both sides pay the fee of the pair from the tokens they receive.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn settle_fill(
+        &mut self,
+        pair_id: &PairId,
+        fill: &Fill,
+        taker_side: Side,
+        buyer_id: &AccountId,
+        buyer_price: BigDecimal,
+        buyer_remaining: BigDecimal,
+        seller_id: &AccountId,
+    ) {
+        let (base, quote) = pair_id;
+        let fees = self.get_fees(pair_id.clone());
+        let (buyer_fee_bps, seller_fee_bps) = match taker_side {
+            Side::Buy => (fees.taker_fee_bps, fees.maker_fee_bps),
+            Side::Sell => (fees.maker_fee_bps, fees.taker_fee_bps),
+        };
+
+        // Computed as a difference, so the lock of the rest of the order stays exact
+        let released =
+            buyer_remaining * buyer_price - (buyer_remaining - fill.amount) * buyer_price;
+        let cost = (fill.amount * fill.price).min(released);
+
+        self.internal_spend_locked(buyer_id, quote, released);
+        self.internal_deposit(buyer_id, quote, released - cost);
+        let received = self.internal_charge_fee(base, fill.amount, buyer_fee_bps);
+        self.internal_deposit(buyer_id, base, received);
+
+        self.internal_spend_locked(seller_id, base, fill.amount);
+        let received = self.internal_charge_fee(quote, cost, seller_fee_bps);
+        self.internal_deposit(seller_id, quote, received);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{
+        big, deposit, order_json, register_pair, set_predecessor, usdt, wnear,
+    };
+    use near_sdk::test_utils::test_env::{alice, bob};
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, PromiseResult, RuntimeFeesConfig, VMConfig};
+
+    // Unlike "test_utils::add_order" doesn't deposit the tokens for the order
+    fn place(
+        contract: &mut Contract,
+        account_id: AccountId,
+        side: &str,
+        price: &str,
+        amount: &str,
+    ) -> U64 {
+        register_pair(contract, "wnear.near", "usdt.near");
+        set_predecessor(account_id.clone());
+        contract.add_order_from_string_3(order_json(&account_id, side, price, amount))
+    }
+
+    fn balance(available: &str, locked: &str) -> Balance {
+        Balance {
+            available: big(available),
+            locked: big(locked),
+        }
+    }
+
+    #[test]
+    fn test_lock_and_cancel() {
+        let mut contract = Contract::new();
+
+        deposit(&mut contract, alice(), usdt(), 100);
+        let order_id = place(&mut contract, alice(), "buy", "1.5", "10");
+        assert_eq!(contract.get_balance(alice(), usdt()), balance("85", "15"));
+
+        set_predecessor(alice());
+        contract.cancel_order(order_id);
+        assert_eq!(contract.get_balance(alice(), usdt()), balance("100", "0"));
+    }
+
+    #[test]
+    fn test_lock_not_enough() {
+        let mut contract = Contract::new();
+
+        deposit(&mut contract, alice(), usdt(), 10);
+        register_pair(&mut contract, "wnear.near", "usdt.near");
+        set_predecessor(alice());
+        let err = contract
+            .add_order_from_string(order_json(&alice(), "buy", "1.5", "10"))
+            .unwrap_err();
+        assert_eq!(err, OrderError::InsufficientBalance(usdt()));
+        assert!(err.to_string().starts_with("E019_INSUFFICIENT_BALANCE"));
+        assert_eq!(contract.get_balance(alice(), usdt()), balance("10", "0"));
+    }
+
+    #[test]
+    fn test_fills_credit_ledger() {
+        let mut contract = Contract::new();
+
+        deposit(&mut contract, alice(), wnear(), 10);
+        deposit(&mut contract, bob(), usdt(), 100);
+
+        // Alice sells 10 wNEAR at 2, Bob buys 4 at up to 2.5 and pays the maker's price
+        place(&mut contract, alice(), "sell", "2", "10");
+        let taker_id = place(&mut contract, bob(), "buy", "2.5", "4");
+        assert!(contract.get_order(taker_id).is_none());
+
+        assert_eq!(contract.get_balance(alice(), wnear()), balance("0", "6"));
+        assert_eq!(contract.get_balance(alice(), usdt()), balance("8", "0"));
+        assert_eq!(contract.get_balance(bob(), wnear()), balance("4", "0"));
+        assert_eq!(contract.get_balance(bob(), usdt()), balance("92", "0"));
+
+        // Bob's bid rests with the rest of its lock at its own price
+        let bid_id = place(&mut contract, bob(), "buy", "1.5", "10");
+        assert_eq!(contract.get_balance(bob(), usdt()), balance("77", "15"));
+
+        // Alice sells into the bid and receives the maker's price
+        deposit(&mut contract, alice(), wnear(), 6);
+        place(&mut contract, alice(), "sell", "1", "6");
+        assert_eq!(contract.get_balance(bob(), usdt()), balance("77", "6"));
+        assert_eq!(contract.get_balance(bob(), wnear()), balance("10", "0"));
+        assert_eq!(contract.get_balance(alice(), usdt()), balance("17", "0"));
+        assert_eq!(contract.get_balance(alice(), wnear()), balance("0", "6"));
+        assert_eq!(contract.get_order(bid_id).unwrap().amount, big("4"));
+    }
+
+    #[test]
+    fn test_withdraw() {
+        let mut contract = Contract::new();
+
+        deposit(&mut contract, alice(), usdt(), 100);
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(alice())
+            .attached_deposit(1)
+            .build());
+        contract.withdraw(usdt(), U128(30));
+        assert_eq!(contract.get_balance(alice(), usdt()), balance("70", "0"));
+
+        // The transfer fails and the callback restores the balance
+        testing_env!(
+            VMContextBuilder::new()
+                .predecessor_account_id(env::current_account_id())
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        contract.resolve_withdraw(alice(), usdt(), U128(30));
+        assert_eq!(contract.get_balance(alice(), usdt()), balance("100", "0"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough available balance")]
+    fn test_withdraw_locked() {
+        let mut contract = Contract::new();
+
+        deposit(&mut contract, alice(), usdt(), 100);
+        place(&mut contract, alice(), "buy", "1", "50");
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(alice())
+            .attached_deposit(1)
+            .build());
+        contract.withdraw(usdt(), U128(60));
+    }
+}
@@ -1,179 +1,506 @@
-use near_sdk::test_utils::test_env::{alice, bob, carol};
-
 use borsh::{BorshDeserialize, BorshSerialize};
 use near_sdk::{
-    collections::UnorderedMap,
+    collections::{LookupMap, UnorderedMap, UnorderedSet, Vector},
+    env,
+    json_types::U64,
+    near_bindgen,
     serde::{Deserialize, Serialize},
-    serde_json, AccountId,
+    serde_json, AccountId, BorshStorageKey, CryptoHash, IntoStorageKey, PanicOnDefault,
 };
-use std::collections::HashMap;
 
-mod big_decimal;
+pub mod big_decimal;
+mod errors;
+pub mod events;
+mod expiry;
+mod fees;
+mod history;
+mod ledger;
+mod market;
+mod matching;
+mod migration;
+mod order_book;
+mod order_list;
+mod owner;
+mod pairs;
+mod receiver;
+mod storage;
+#[cfg(test)]
+mod test_utils;
+mod time_in_force;
+mod trades;
+mod views;
 
 use crate::big_decimal::*;
+pub use crate::errors::OrderError;
+use crate::events::{Event, OrderEvent};
+pub use crate::fees::FeeSchedule;
+use crate::history::OrderHistory;
+pub use crate::history::OrderStatus;
+pub use crate::ledger::Balance;
+pub use crate::market::{MarketOrder, MarketOrderResult};
+use crate::order_book::OrderBook;
+pub use crate::order_book::{OrderBookView, PriceLevel, Side};
+use crate::order_list::OrderList;
+pub use crate::owner::Role;
+pub use crate::pairs::Pair;
+use crate::storage::AccountStorage;
+pub use crate::time_in_force::TimeInForce;
+pub use crate::trades::Trade;
+pub use crate::views::{OrderFilter, OrderSort};
+
+pub type PairId = (AccountId, AccountId);
+
+/// Page size of the paginated views when no limit is given
+pub(crate) const DEFAULT_LIMIT: u64 = 100;
+
+/// Prefixes of every persistent collection owned by `Contract`.
+/// Each collection must be built from its own variant, never from a raw byte string.
+#[derive(BorshStorageKey, BorshSerialize, Clone, Debug)]
+pub(crate) enum StorageKey {
+    Orders,
+    RefOrders,
+    AccountOrders { account_hash: CryptoHash },
+    PairOrders { pair_hash: CryptoHash },
+    OrderIndex,
+    OrderBooks,
+    Bids { pair_hash: CryptoHash },
+    Asks { pair_hash: CryptoHash },
+    Trades,
+    TradesByAccount,
+    AccountTrades { account_hash: CryptoHash },
+    TradesByPair,
+    PairTrades { pair_hash: CryptoHash },
+    Balances,
+    AccountBalances { account_hash: CryptoHash },
+    StorageAccounts,
+    Pairs,
+    FeeVault,
+    Operators,
+    Guardians,
+    OrderHistory,
+    AccountHistory { account_hash: CryptoHash },
+    AccountOrderLinks { account_hash: CryptoHash },
+    PairOrderLinks { pair_hash: CryptoHash },
+}
 
-type PairId = (AccountId, AccountId);
-
-#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(crate = "near_sdk::serde")]
-struct Order {
-    account_id: AccountId,
-    amount: BigDecimal,
-    sell_token: AccountId,
-    buy_token: AccountId,
+pub struct Order {
+    pub account_id: AccountId,
+    /// Amount of the base token left in the order
+    pub amount: BigDecimal,
+    pub sell_token: AccountId,
+    pub buy_token: AccountId,
+    /// Amount of the quote token paid for one base token
+    pub price: BigDecimal,
+    /// Derived from the sold token when the order is added,
+    /// the value in the payload is ignored and can be omitted
+    #[serde(default)]
+    pub side: Side,
+    /// Only applies when the order is added, so it isn't stored
+    #[serde(default)]
+    #[borsh_skip]
+    pub time_in_force: TimeInForce,
+    /// Set by the contract, like the fields below
+    #[serde(default)]
+    pub status: OrderStatus,
+    /// Amount of the base token filled so far
+    #[serde(default)]
+    pub filled_amount: BigDecimal,
+    /// Block timestamp in nanoseconds
+    #[serde(default = "Order::no_timestamp")]
+    pub created_at: U64,
+    /// Block timestamp in nanoseconds after which the order can't be matched
+    /// and can be pruned by anyone, never when omitted
+    #[serde(default)]
+    pub expires_at: Option<U64>,
+}
+
+impl Order {
+    fn no_timestamp() -> U64 {
+        U64(0)
+    }
+
+    /// Amount of "sell_token" the order can spend: the base amount for a sell order
+    /// and the quote amount at the order's price for a buy order.
+    pub fn sell_amount(&self) -> BigDecimal {
+        match self.side {
+            Side::Sell => self.amount,
+            Side::Buy => self.amount * self.price,
+        }
+    }
+
+    /// Whether a resting order at `price` can be matched with this order.
+    pub fn crosses(&self, price: BigDecimal) -> bool {
+        match self.side {
+            Side::Buy => price <= self.price,
+            Side::Sell => price >= self.price,
+        }
+    }
+
+    pub fn is_expired(&self, timestamp: u64) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at.0 <= timestamp)
+    }
+
+    /// (base, quote) of the book the order belongs to.
+    /// The base is the token being sold by a sell order and bought by a buy order.
+    pub fn book_pair_id(&self) -> PairId {
+        match self.side {
+            Side::Sell => (self.sell_token.clone(), self.buy_token.clone()),
+            Side::Buy => (self.buy_token.clone(), self.sell_token.clone()),
+        }
+    }
 }
 
-#[derive(Debug, BorshDeserialize, BorshSerialize)]
-struct Contract {
+#[near_bindgen]
+#[derive(Debug, BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct Contract {
+    /// The account that initialized the contract, until it transfers the ownership
+    owner_id: AccountId,
+    /// Proposed by the owner, becomes the owner once it accepts
+    pending_owner_id: Option<AccountId>,
+    /// Manage the pairs and their fees along with the owner
+    operators: UnorderedSet<AccountId>,
+    /// Can pause the contract along with the owner
+    guardians: UnorderedSet<AccountId>,
+    /// No new orders are accepted while paused
+    paused: bool,
     order_nonce: u64,
-    orders: UnorderedMap<AccountId, HashMap<u64, Order>>,
-    ref_orders: UnorderedMap<PairId, HashMap<u64, Order>>,
+    orders: UnorderedMap<AccountId, OrderList>,
+    ref_orders: UnorderedMap<PairId, OrderList>,
+    /// order_id -> (owner, pair), for clients that only know the id
+    order_index: LookupMap<u64, (AccountId, PairId)>,
+    /// Filled and cancelled orders of each account, the latest "history_retention" ones
+    order_history: LookupMap<AccountId, OrderHistory>,
+    history_retention: u64,
+    /// (base, quote) -> bids and asks in price-time priority
+    order_books: LookupMap<PairId, OrderBook>,
+    /// Append-only list of every fill
+    trades: Vector<Trade>,
+    /// Indexes in "trades" of the trades where the account was the maker or the taker
+    trades_by_account: LookupMap<AccountId, Vector<u64>>,
+    /// Indexes in "trades" of the trades of the (base, quote) pair
+    trades_by_pair: LookupMap<PairId, Vector<u64>>,
+    /// account -> token -> available and locked amounts
+    balances: LookupMap<AccountId, UnorderedMap<AccountId, Balance>>,
+    /// NEP-145 storage deposits and the bytes each account pays for
+    storage_accounts: LookupMap<AccountId, AccountStorage>,
+    /// (base, quote) -> the pair registered by the owner
+    pairs: UnorderedMap<PairId, Pair>,
+    /// Fees of the pairs without their own schedule
+    default_fees: FeeSchedule,
+    /// token -> collected fees
+    fee_vault: UnorderedMap<AccountId, BigDecimal>,
 }
 
-impl Contract {
-    fn new() -> Self {
-        Self {
-            order_nonce: 0,
-            orders: UnorderedMap::new(b"m"),
-            ref_orders: UnorderedMap::new(b"m"),
+/// Panics if one of the prefixes is equal to or starts with another one,
+/// i.e. if two collections could read and write the same storage keys.
+#[cfg(debug_assertions)]
+fn assert_disjoint_prefixes(prefixes: &[Vec<u8>]) {
+    for (i, a) in prefixes.iter().enumerate() {
+        for b in &prefixes[i + 1..] {
+            assert!(
+                !a.starts_with(b) && !b.starts_with(a),
+                "Storage prefixes {:?} and {:?} overlap",
+                a,
+                b
+            );
         }
     }
+}
 
-    fn new_1() -> Self {
-        let pair_id: PairId = ("usdt.near".parse().unwrap(), "wnear.near".parse().unwrap());
+#[near_bindgen]
+impl Contract {
+    #[init]
+    pub fn new() -> Self {
+        migration::write_state_version();
+        Self::from_storage_keys()
+    }
 
-        let map = HashMap::from([
-            (
-                1,
-                Order {
-                    account_id: alice(),
-                    amount: BigDecimal::from(1),
-                    sell_token: pair_id.0.clone(),
-                    buy_token: pair_id.1.clone(),
-                },
-            ),
-            (
-                2,
-                Order {
-                    account_id: alice(),
-                    amount: BigDecimal::from(1),
-                    sell_token: pair_id.0.clone(),
-                    buy_token: pair_id.1.clone(),
-                },
-            ),
-            (
-                3,
-                Order {
-                    account_id: alice(),
-                    amount: BigDecimal::from(1),
-                    sell_token: pair_id.0.clone(),
-                    buy_token: pair_id.1.clone(),
-                },
-            ),
-        ]);
+    // orders are added for "orders", "ref_orders" and "order_index"
+    #[payable]
+    pub fn add_order_from_string_1(&mut self, order: String) -> U64 {
+        self.add_order_from_string(order)
+            .unwrap_or_else(|err| env::panic_str(&err.to_string()))
+            .into()
+    }
 
-        let mut orders = UnorderedMap::<AccountId, HashMap<u64, Order>>::new(b"m");
-        orders.insert(&alice(), &map);
+    // the same as "add_order_from_string_1"
+    #[payable]
+    pub fn add_order_from_string_2(&mut self, order: String) -> U64 {
+        self.add_order_from_string(order)
+            .unwrap_or_else(|err| env::panic_str(&err.to_string()))
+            .into()
+    }
 
-        let ref_orders = UnorderedMap::<PairId, HashMap<u64, Order>>::new(b"m");
+    // the same as "add_order_from_string_1"
+    #[payable]
+    pub fn add_order_from_string_3(&mut self, order: String) -> U64 {
+        self.add_order_from_string(order)
+            .unwrap_or_else(|err| env::panic_str(&err.to_string()))
+            .into()
+    }
 
-        Self {
-            order_nonce: 3,
-            orders,
-            ref_orders,
+    /// Moves the order from "orders", "ref_orders", "order_index" and the order book
+    /// to the history, the locked tokens are returned to the available balance.
+    /// Only the owner of the order can cancel it.
+    pub fn cancel_order(&mut self, order_id: U64) -> Order {
+        let (account_id, pair_id) = self.order_index.get(&order_id.0).expect("Order not found");
+        assert_eq!(
+            account_id,
+            env::predecessor_account_id(),
+            "Only the owner can cancel the order"
+        );
+
+        let initial_storage_usage = env::storage_usage();
+        let mut order = self.internal_remove_order(&account_id, &pair_id, order_id.0);
+        self.internal_unlock(&account_id, &order.sell_token, order.sell_amount());
+        order.status = OrderStatus::Cancelled;
+        self.internal_record_closed_order(&account_id, order_id.0, &order);
+        self.internal_update_storage(&account_id, initial_storage_usage);
+        Event::OrderCancelled(vec![OrderEvent::new(order_id.0, &order)]).emit();
+
+        order
+    }
+
+    pub fn get_order(&self, order_id: U64) -> Option<Order> {
+        let (account_id, _) = self.order_index.get(&order_id.0)?;
+        self.orders.get(&account_id)?.get(&order_id.0)
+    }
+}
+
+impl Contract {
+    /// Builds every collection of the contract from its `StorageKey`.
+    fn from_storage_keys() -> Self {
+        let mut prefixes = Vec::new();
+        let mut prefix = |key: StorageKey| {
+            let prefix = key.into_storage_key();
+            prefixes.push(prefix.clone());
+            prefix
+        };
+
+        let contract = Self {
+            owner_id: env::predecessor_account_id(),
+            pending_owner_id: None,
+            operators: UnorderedSet::new(prefix(StorageKey::Operators)),
+            guardians: UnorderedSet::new(prefix(StorageKey::Guardians)),
+            paused: false,
+            order_nonce: 0,
+            orders: UnorderedMap::new(prefix(StorageKey::Orders)),
+            ref_orders: UnorderedMap::new(prefix(StorageKey::RefOrders)),
+            order_index: LookupMap::new(prefix(StorageKey::OrderIndex)),
+            order_history: LookupMap::new(prefix(StorageKey::OrderHistory)),
+            history_retention: history::DEFAULT_HISTORY_RETENTION,
+            order_books: LookupMap::new(prefix(StorageKey::OrderBooks)),
+            trades: Vector::new(prefix(StorageKey::Trades)),
+            trades_by_account: LookupMap::new(prefix(StorageKey::TradesByAccount)),
+            trades_by_pair: LookupMap::new(prefix(StorageKey::TradesByPair)),
+            balances: LookupMap::new(prefix(StorageKey::Balances)),
+            storage_accounts: LookupMap::new(prefix(StorageKey::StorageAccounts)),
+            pairs: UnorderedMap::new(prefix(StorageKey::Pairs)),
+            default_fees: FeeSchedule::default(),
+            fee_vault: UnorderedMap::new(prefix(StorageKey::FeeVault)),
+        };
+
+        #[cfg(debug_assertions)]
+        assert_disjoint_prefixes(&prefixes);
+
+        contract
+    }
+
+    /// The NEAR attached to the call is added to the caller's storage deposit.
+    fn add_order_from_string(&mut self, order: String) -> Result<u64, OrderError> {
+        let account_id = env::predecessor_account_id();
+        let order = self.internal_parse_order(&account_id, &order)?;
+        self.internal_add_storage_deposit(&account_id, env::attached_deposit());
+
+        self.internal_add_order(&account_id, order)
+    }
+
+    /// Parses an order submitted by `account_id`, puts it on its market
+    /// and checks that its time in force allows to add it.
+    pub(crate) fn internal_parse_order(
+        &self,
+        account_id: &AccountId,
+        order: &str,
+    ) -> Result<Order, OrderError> {
+        self.check_not_paused()?;
+        let mut order = Order::from_json(order)?;
+        order.validate_owner(account_id)?;
+        self.internal_normalize_order(&mut order)?;
+        if order.is_expired(env::block_timestamp()) {
+            return Err(OrderError::Expired);
         }
+        self.check_time_in_force(&order)?;
+        Ok(order)
     }
 
-    // orders are added only for "orders"
-    fn add_order_from_string_1(&mut self, account_id: AccountId, order: String) {
+    /// Locks the tokens of the order, matches it against the book
+    /// and places the unfilled remainder, or records the order as filled.
+    /// The remainder of an immediate-or-cancel order is cancelled instead.
+    /// The storage grown by the call is charged to the owner of the order.
+    pub(crate) fn internal_add_order(
+        &mut self,
+        account_id: &AccountId,
+        mut order: Order,
+    ) -> Result<u64, OrderError> {
+        let initial_storage_usage = env::storage_usage();
+        order.status = OrderStatus::Open;
+        order.filled_amount = BigDecimal::zero();
+        order.created_at = env::block_timestamp().into();
+        self.internal_lock(account_id, &order.sell_token, order.sell_amount())?;
         self.order_nonce += 1;
         let order_id = self.order_nonce;
+        Event::OrderCreated(vec![OrderEvent::new(order_id, &order)]).emit();
+
+        let (_, released_storage) = self.match_order(account_id, order_id, &mut order);
+        if order.amount == BigDecimal::zero() {
+            self.internal_record_closed_order(account_id, order_id, &order);
+        } else if order.time_in_force == TimeInForce::Ioc {
+            self.internal_unlock(account_id, &order.sell_token, order.sell_amount());
+            order.status = OrderStatus::Cancelled;
+            self.internal_record_closed_order(account_id, order_id, &order);
+            Event::OrderCancelled(vec![OrderEvent::new(order_id, &order)]).emit();
+        } else {
+            self.internal_place_order(account_id, order_id, order);
+        }
 
-        let order: Order = serde_json::from_str(order.clone().as_str()).unwrap();
+        // The storage of the filled maker orders is already given back to their owners
+        self.internal_update_storage(account_id, initial_storage_usage - released_storage);
 
-        self.insert_order(&account_id, order.clone(), order_id);
-        // self.insert_ref_order(
-        //     &(order.sell_token.clone(), order.buy_token.clone()),
-        //     order,
-        //     order_id,
-        // );
+        Ok(order_id)
     }
 
-    // orders are added only for "ref_orders"
-    fn add_order_from_string_2(&mut self, account_id: AccountId, order: String) {
-        self.order_nonce += 1;
-        let order_id = self.order_nonce;
+    /// Writes the order to "orders", "ref_orders", "order_index" and the order book
+    /// within one call, so the indexes never disagree.
+    fn internal_place_order(&mut self, account_id: &AccountId, order_id: u64, order: Order) {
+        let pair_id = order.book_pair_id();
 
-        let order: Order = serde_json::from_str(order.clone().as_str()).unwrap();
+        self.insert_book_order(order_id, &order);
+        self.insert_order(account_id, order.clone(), order_id);
+        self.insert_ref_order(&pair_id, order, order_id);
+        self.order_index
+            .insert(&order_id, &(account_id.clone(), pair_id));
+    }
 
-        // self.insert_order(&account_id, order.clone(), order_id);
-        self.insert_ref_order(
-            &(order.sell_token.clone(), order.buy_token.clone()),
-            order,
-            order_id,
-        );
+    /// Overwrites an order that is already placed, e.g. after a partial fill.
+    /// The price and the side of the order must not change.
+    fn internal_update_order(
+        &mut self,
+        account_id: &AccountId,
+        pair_id: &PairId,
+        order_id: u64,
+        order: Order,
+    ) {
+        self.insert_book_order(order_id, &order);
+        self.insert_order(account_id, order.clone(), order_id);
+        self.insert_ref_order(pair_id, order, order_id);
     }
 
-    // orders are added for "orders" and "ref_orders"
-    fn add_order_from_string_3(&mut self, account_id: AccountId, order: String) {
-        self.order_nonce += 1;
-        let order_id = self.order_nonce;
+    /// Removes the order from "orders", "ref_orders", "order_index" and the order book.
+    fn internal_remove_order(
+        &mut self,
+        account_id: &AccountId,
+        pair_id: &PairId,
+        order_id: u64,
+    ) -> Order {
+        let order = self.remove_order(account_id, order_id);
+        self.remove_ref_order(pair_id, order_id);
+        self.remove_book_order(order_id, &order);
+        self.order_index.remove(&order_id);
+
+        order
+    }
 
-        let order: Order = serde_json::from_str(order.clone().as_str()).unwrap();
+    #[cfg(test)]
+    fn new_1() -> Self {
+        use near_sdk::test_utils::test_env::alice;
 
-        self.insert_order(&account_id, order.clone(), order_id);
-        self.insert_ref_order(
-            &(order.sell_token.clone(), order.buy_token.clone()),
-            order,
-            order_id,
-        );
+        let pair_id: PairId = ("usdt.near".parse().unwrap(), "wnear.near".parse().unwrap());
+
+        let mut contract = Self::new();
+        for order_id in 1..=3 {
+            let order = Order {
+                account_id: alice(),
+                amount: BigDecimal::from(1),
+                sell_token: pair_id.0.clone(),
+                buy_token: pair_id.1.clone(),
+                price: BigDecimal::from(1),
+                side: Side::Buy,
+                time_in_force: TimeInForce::Gtc,
+                status: OrderStatus::Open,
+                filled_amount: BigDecimal::zero(),
+                created_at: U64(0),
+                expires_at: None,
+            };
+            contract.insert_order(&alice(), order, order_id);
+        }
+        contract.order_nonce = 3;
+        contract
     }
 
+    // Only the inserted order, the link to the last one and the header of the inner list
+    // are written, the other orders of the account are not read.
     fn insert_order(&mut self, account_id: &AccountId, order: Order, order_id: u64) {
-        let mut get_orders = self.orders.get(account_id).unwrap_or_default();
+        let mut get_orders = self
+            .orders
+            .get(account_id)
+            .unwrap_or_else(|| OrderList::for_account(account_id));
 
-        get_orders.insert(order_id, order);
+        get_orders.insert(&order_id, &order);
         self.orders.insert(account_id, &get_orders);
     }
 
-    fn insert_ref_order(&mut self, pair_id: &PairId, order: Order, order_id: u64) {
-        let mut get_ref_orders = self.ref_orders.get(pair_id).unwrap_or_default();
+    // An empty map is removed from "orders" so its storage is released
+    fn remove_order(&mut self, account_id: &AccountId, order_id: u64) -> Order {
+        let mut get_orders = self.orders.get(account_id).expect("Order not found");
+        let order = get_orders.remove(&order_id).expect("Order not found");
 
-        get_ref_orders.insert(order_id, order);
-        self.ref_orders.insert(pair_id, &get_ref_orders);
+        if get_orders.is_empty() {
+            self.orders.remove(account_id);
+        } else {
+            self.orders.insert(account_id, &get_orders);
+        }
+        order
     }
 
-    fn view_orders(&self, account_id: &AccountId) -> Vec<(u64, Order)> {
-        let mut orders = self.orders.get(account_id).unwrap_or_default();
+    fn insert_ref_order(&mut self, pair_id: &PairId, order: Order, order_id: u64) {
+        let mut get_ref_orders = self
+            .ref_orders
+            .get(pair_id)
+            .unwrap_or_else(|| OrderList::for_pair(pair_id));
 
-        let mut sort_orders = orders.into_iter().collect::<Vec<(u64, Order)>>();
-        sort_orders.sort_by(|a, b| a.0.cmp(&b.0));
-        sort_orders
+        get_ref_orders.insert(&order_id, &order);
+        self.ref_orders.insert(pair_id, &get_ref_orders);
     }
 
-    fn view_ref_orders(&self, pair_id: &PairId) -> Vec<(u64, Order)> {
-        let mut ref_orders = self
-            .ref_orders
-            .get(pair_id)
-            .unwrap()
-            .into_iter()
-            .collect::<Vec<(u64, Order)>>();
-        ref_orders.sort_by(|a, b| a.0.cmp(&b.0));
-        ref_orders
+    fn remove_ref_order(&mut self, pair_id: &PairId, order_id: u64) -> Order {
+        let mut get_ref_orders = self.ref_orders.get(pair_id).expect("Order not found");
+        let order = get_ref_orders.remove(&order_id).expect("Order not found");
+
+        if get_ref_orders.is_empty() {
+            self.ref_orders.remove(pair_id);
+        } else {
+            self.ref_orders.insert(pair_id, &get_ref_orders);
+        }
+        order
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_utils::{fund_accounts, set_predecessor};
     use near_sdk::test_utils::test_env::{alice, bob, carol};
 
-    // Test for adding only to the field "orders" 
+    // Test for adding through "add_order_from_string_1"
     // Three users: Alice, Bob, Carol
     // One pair: "usdt.near|wnear.near"
     #[test]
     fn test_1() {
         let mut contract = Contract::new();
+        fund_accounts(&mut contract);
 
         // Must be "0"
         println!("Number of users before- {}", contract.orders.len());
@@ -182,24 +509,27 @@ mod tests {
         println!("-------------------");
 
         // Orders for the "usdt.near|wnear.near" pair from users: Alice, Bob, Carol
-        // orders are added only for "orders"
+        // orders are added for "orders" and "ref_orders"
         for count in 1..=9 {
             if count <= 3 {
-                let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\"}".to_string();
+                let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
                 let account_id = alice();
-                contract.add_order_from_string_1(account_id, order_alice);
+                set_predecessor(account_id);
+                contract.add_order_from_string_1(order_alice);
             } else if count <= 6 {
-                let order_bob = "{\"account_id\":\"bob.near\",\"amount\":\"300\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\"}".to_string();
-                contract.add_order_from_string_1(bob(), order_bob.clone());
+                let order_bob = "{\"account_id\":\"bob.near\",\"amount\":\"300\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+                set_predecessor(bob());
+                contract.add_order_from_string_1(order_bob.clone());
             } else {
-                let order = "{\"account_id\":\"bob.near\",\"amount\":\"500\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\"}".to_string();
+                let order = "{\"account_id\":\"carol.near\",\"amount\":\"500\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
                 let account_id = carol();
-                contract.add_order_from_string_1(account_id, order.clone());
+                set_predecessor(account_id);
+                contract.add_order_from_string_1(order.clone());
             }
         }
         // Must be "3"
         println!("Number of users after- {}", contract.orders.len());
-        // Must be "0"
+        // Must be "1"
         println!("Number of pairs afters - {}", contract.ref_orders.len());
         println!("-------------------");
 
@@ -221,24 +551,25 @@ mod tests {
         println!("-------------------");
 
         // Must be "3" (#1...#3)
-        println!("{:#?}", contract.view_orders(&alice()));
+        println!("{:#?}", contract.view_orders(alice(), None, None, None));
         println!("-------------------");
         // Must be "3" (#4...#6)
-        println!("{:#?}", contract.view_orders(&bob()));
+        println!("{:#?}", contract.view_orders(bob(), None, None, None));
         println!("-------------------");
         // Must be "3" (#7...#9)
-        println!("{:#?}", contract.view_orders(&carol()));
+        println!("{:#?}", contract.view_orders(carol(), None, None, None));
     }
 
-    // Test for adding only to the field "ref_orders"
+    // Test for adding through "add_order_from_string_2"
     // Three users: Alice, Bob, Carol
     // One pair: "usdt.near|wnear.near"
     #[test]
     fn test_2() {
         let mut contract = Contract::new();
+        fund_accounts(&mut contract);
 
         let pair_id: PairId = ("usdt.near".parse().unwrap(), "wnear.near".parse().unwrap());
-        
+
         // Must be "0"
         println!("Number of users before- {}", contract.orders.len());
         // Must be "0"
@@ -246,21 +577,24 @@ mod tests {
         println!("-------------------");
 
         // Orders for the "usdt.near|wnear.near" pair from users: Alice, Bob, Carol
-        // orders are added only for "ref_orders"
+        // orders are added for "orders" and "ref_orders"
         for count in 1..=9 {
             if count <= 3 {
-                let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\"}".to_string();
-                contract.add_order_from_string_2(alice(), order_alice);
+                let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+                set_predecessor(alice());
+                contract.add_order_from_string_2(order_alice);
             } else if count <= 6 {
-                let order_bob = "{\"account_id\":\"bob.near\",\"amount\":\"300\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\"}".to_string();
-                contract.add_order_from_string_2(bob(), order_bob);
+                let order_bob = "{\"account_id\":\"bob.near\",\"amount\":\"300\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+                set_predecessor(bob());
+                contract.add_order_from_string_2(order_bob);
             } else {
-                let order_carol = "{\"account_id\":\"bob.near\",\"amount\":\"500\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\"}".to_string();
-                contract.add_order_from_string_2(carol(), order_carol);
+                let order_carol = "{\"account_id\":\"carol.near\",\"amount\":\"500\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+                set_predecessor(carol());
+                contract.add_order_from_string_2(order_carol);
             }
         }
 
-        // Must be "0"
+        // Must be "3"
         println!("Number of users after- {}", contract.orders.len());
         // Must be "1"
         println!("Number of pairs afters - {}", contract.ref_orders.len());
@@ -269,20 +603,26 @@ mod tests {
         // Must be "9"
         println!(
             "Number of orders from usdt.near|wnear.near - {}",
-            contract.ref_orders.get(&pair_id.clone()).unwrap().len()
+            contract
+                .view_ref_orders(pair_id.clone(), None, None, None)
+                .len()
         );
         println!("-------------------");
-        
+
         // Must be "9" (#1...#9)
-        println!("{:#?}", contract.view_ref_orders(&pair_id));
+        println!(
+            "{:#?}",
+            contract.view_ref_orders(pair_id.clone(), None, None, None)
+        );
     }
 
-    // Test for adding to the fields "orders" and "ref_orders"
+    // Test for adding through "add_order_from_string_3"
     // Three users: Alice, Bob, Carol
     // One pair: "usdt.near|wnear.near"
     #[test]
     fn test_3() {
         let mut contract = Contract::new();
+        fund_accounts(&mut contract);
 
         let pair_id: PairId = ("usdt.near".parse().unwrap(), "wnear.near".parse().unwrap());
 
@@ -296,14 +636,17 @@ mod tests {
         // orders are added for "orders" and "ref_orders"
         for count in 1..=9 {
             if count <= 3 {
-                let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\"}".to_string();
-                contract.add_order_from_string_3(alice(), order_alice);
+                let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+                set_predecessor(alice());
+                contract.add_order_from_string_3(order_alice);
             } else if count <= 6 {
-                let order_bob = "{\"account_id\":\"bob.near\",\"amount\":\"300\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\"}".to_string();
-                contract.add_order_from_string_3(bob(), order_bob);
+                let order_bob = "{\"account_id\":\"bob.near\",\"amount\":\"300\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+                set_predecessor(bob());
+                contract.add_order_from_string_3(order_bob);
             } else {
-                let order_carol = "{\"account_id\":\"bob.near\",\"amount\":\"500\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\"}".to_string();
-                contract.add_order_from_string_3(carol(), order_carol);
+                let order_carol = "{\"account_id\":\"carol.near\",\"amount\":\"500\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+                set_predecessor(carol());
+                contract.add_order_from_string_3(order_carol);
             }
         }
 
@@ -333,29 +676,35 @@ mod tests {
         // Must be "9"
         println!(
             "Number of orders from usdt.near|wnear.near - {}",
-            contract.ref_orders.get(&pair_id.clone()).unwrap().len()
+            contract
+                .view_ref_orders(pair_id.clone(), None, None, None)
+                .len()
         );
         println!("-------------------");
-        
+
         // Must be "3" (#1...#3)
-        println!("{:#?}", contract.view_orders(&alice()));
+        println!("{:#?}", contract.view_orders(alice(), None, None, None));
         println!("-------------------");
         // Must be "3" (#4...#6)
-        println!("{:#?}", contract.view_orders(&bob()));
+        println!("{:#?}", contract.view_orders(bob(), None, None, None));
         println!("-------------------");
         // Must be "3" (#7...#9)
-        println!("{:#?}", contract.view_orders(&carol()));
+        println!("{:#?}", contract.view_orders(carol(), None, None, None));
         println!("-------------------");
         // Must be "9" (#1...#9)
-        println!("{:#?}", contract.view_ref_orders(&pair_id));
+        println!(
+            "{:#?}",
+            contract.view_ref_orders(pair_id.clone(), None, None, None)
+        );
     }
 
-    // Test for adding to the fields "ref_orders"  But Alice already has three orders at initialization in the field "orders"
+    // Test for adding through "add_order_from_string_2". But Alice already has three orders at initialization in the field "orders"
     // Three users: Alice, Bob, Carol
     // One pair: "usdt.near|wnear.near"
     #[test]
     fn test_4() {
         let mut contract = Contract::new_1();
+        fund_accounts(&mut contract);
 
         let pair_id: PairId = ("usdt.near".parse().unwrap(), "wnear.near".parse().unwrap());
 
@@ -373,25 +722,28 @@ mod tests {
         println!("-------------------");
 
         // Must be "3" (#1...#3)
-        println!("{:#?}", contract.view_orders(&alice()));
+        println!("{:#?}", contract.view_orders(alice(), None, None, None));
         println!("-------------------");
 
         // Orders for the "usdt.near|wnear.near" pair from users: Alice, Bob, Carol
-        // orders are added only for "ref_orders"
+        // orders are added for "orders" and "ref_orders"
         for count in 1..=9 {
             if count <= 3 {
-                let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\"}".to_string();
-                contract.add_order_from_string_2(alice(), order_alice);
+                let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+                set_predecessor(alice());
+                contract.add_order_from_string_2(order_alice);
             } else if count <= 6 {
-                let order_bob = "{\"account_id\":\"bob.near\",\"amount\":\"300\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\"}".to_string();
-                contract.add_order_from_string_2(bob(), order_bob);
+                let order_bob = "{\"account_id\":\"bob.near\",\"amount\":\"300\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+                set_predecessor(bob());
+                contract.add_order_from_string_2(order_bob);
             } else {
-                let order_carol = "{\"account_id\":\"bob.near\",\"amount\":\"500\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\"}".to_string();
-                contract.add_order_from_string_2(carol(), order_carol);
+                let order_carol = "{\"account_id\":\"carol.near\",\"amount\":\"500\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+                set_predecessor(carol());
+                contract.add_order_from_string_2(order_carol);
             }
         }
 
-        // Must be "1"
+        // Must be "3"
         println!("Number of users after- {}", contract.orders.len());
         // Must be "1"
         println!("Number of pairs afters - {}", contract.ref_orders.len());
@@ -403,26 +755,32 @@ mod tests {
             contract.orders.get(&alice()).unwrap().len()
         );
         println!("-------------------");
-        
+
         // Must be "9"
         println!(
             "Number of orders from usdt.near|wnear.near - {}",
-            contract.ref_orders.get(&pair_id.clone()).unwrap().len()
+            contract
+                .view_ref_orders(pair_id.clone(), None, None, None)
+                .len()
         );
         println!("-------------------");
         // Must be "6" (#1...#6)
-        println!("{:#?}", contract.view_orders(&alice()));
+        println!("{:#?}", contract.view_orders(alice(), None, None, None));
         println!("-------------------");
         // Must be "9" (#4...#12)
-        println!("{:#?}", contract.view_ref_orders(&pair_id));
+        println!(
+            "{:#?}",
+            contract.view_ref_orders(pair_id.clone(), None, None, None)
+        );
     }
 
-    // Test for adding only to the field "orders"
+    // Test for adding through "add_order_from_string_1"
     // Three users: Alice, Bob, Carol
-    // Two pairs: "usdt.near|wnear.near" and "wnear.near|usdt.near"
+    // Both directions of one market: "usdt.near|wnear.near" and "wnear.near|usdt.near"
     #[test]
     fn test_5() {
         let mut contract = Contract::new();
+        fund_accounts(&mut contract);
 
         // Must be "0"
         println!("Number of users before- {}", contract.orders.len());
@@ -431,40 +789,47 @@ mod tests {
         println!("-------------------");
 
         // Orders for the "usdt.near|wnear.near" pair from users: Alice, Bob, Carol
-        // orders are added only for "orders"
+        // orders are added for "orders" and "ref_orders"
         for count in 1..=9 {
             if count <= 3 {
-                let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\"}".to_string();
+                let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
                 let account_id = alice();
-                contract.add_order_from_string_1(account_id, order_alice);
+                set_predecessor(account_id);
+                contract.add_order_from_string_1(order_alice);
             } else if count <= 6 {
-                let order_bob = "{\"account_id\":\"bob.near\",\"amount\":\"300\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\"}".to_string();
-                contract.add_order_from_string_1(bob(), order_bob.clone());
+                let order_bob = "{\"account_id\":\"bob.near\",\"amount\":\"300\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+                set_predecessor(bob());
+                contract.add_order_from_string_1(order_bob.clone());
             } else {
-                let order = "{\"account_id\":\"bob.near\",\"amount\":\"500\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\"}".to_string();
+                let order = "{\"account_id\":\"carol.near\",\"amount\":\"500\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
                 let account_id = carol();
-                contract.add_order_from_string_1(account_id, order.clone());
+                set_predecessor(account_id);
+                contract.add_order_from_string_1(order.clone());
             }
         }
 
         // Orders for the "wnear.near|usdt.near" pair from users: Alice, Bob, Carol
-        // orders are added only for "orders"
+        // asking more than the bids above, so they rest in the same book
+        // orders are added for "orders" and "ref_orders"
         for count in 1..=9 {
             if count <= 3 {
-                let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"wnear.near\",\"buy_token\":\"usdt.near\"}".to_string();
-                contract.add_order_from_string_1(alice(), order_alice);
+                let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"wnear.near\",\"buy_token\":\"usdt.near\",\"price\":\"2\",\"side\":\"sell\"}".to_string();
+                set_predecessor(alice());
+                contract.add_order_from_string_1(order_alice);
             } else if count <= 6 {
-                let order_bob = "{\"account_id\":\"bob.near\",\"amount\":\"300\",\"sell_token\":\"wnear.near\",\"buy_token\":\"usdt.near\"}".to_string();
-                contract.add_order_from_string_1(bob(), order_bob);
+                let order_bob = "{\"account_id\":\"bob.near\",\"amount\":\"300\",\"sell_token\":\"wnear.near\",\"buy_token\":\"usdt.near\",\"price\":\"2\",\"side\":\"sell\"}".to_string();
+                set_predecessor(bob());
+                contract.add_order_from_string_1(order_bob);
             } else {
-                let order_carol = "{\"account_id\":\"bob.near\",\"amount\":\"500\",\"sell_token\":\"wnear.near\",\"buy_token\":\"usdt.near\"}".to_string();
-                contract.add_order_from_string_1(carol(), order_carol);
+                let order_carol = "{\"account_id\":\"carol.near\",\"amount\":\"500\",\"sell_token\":\"wnear.near\",\"buy_token\":\"usdt.near\",\"price\":\"2\",\"side\":\"sell\"}".to_string();
+                set_predecessor(carol());
+                contract.add_order_from_string_1(order_carol);
             }
         }
 
         // Must be "3"
         println!("Number of users after- {}", contract.orders.len());
-        // Must be "0"
+        // Must be "1"
         println!("Number of pairs afters - {}", contract.ref_orders.len());
         println!("-------------------");
 
@@ -485,21 +850,22 @@ mod tests {
         );
         println!("-------------------");
         // Must be "6" (#1...#3, #10...#12)
-        println!("{:#?}", contract.view_orders(&alice()));
+        println!("{:#?}", contract.view_orders(alice(), None, None, None));
         println!("-------------------");
         // Must be "6" (#4...#6, #13...#15)
-        println!("{:#?}", contract.view_orders(&bob()));
+        println!("{:#?}", contract.view_orders(bob(), None, None, None));
         println!("-------------------");
         // Must be "6" (#7...#9, #16...#18)
-        println!("{:#?}", contract.view_orders(&carol()));
+        println!("{:#?}", contract.view_orders(carol(), None, None, None));
     }
 
-    // Test for adding only to the field "ref_orders"
+    // Test for adding through "add_order_from_string_2"
     // Three users: Alice, Bob, Carol
-    // Two pairs: "usdt.near|wnear.near" and "wnear.near|usdt.near"
+    // Both directions of one market: "usdt.near|wnear.near" and "wnear.near|usdt.near"
     #[test]
     fn test_6() {
         let mut contract = Contract::new();
+        fund_accounts(&mut contract);
 
         let pair_id_one: PairId = ("usdt.near".parse().unwrap(), "wnear.near".parse().unwrap());
         let pair_id_two: PairId = ("wnear.near".parse().unwrap(), "usdt.near".parse().unwrap());
@@ -511,64 +877,82 @@ mod tests {
         println!("-------------------");
 
         // Orders for the "usdt.near|wnear.near" pair from users: Alice, Bob, Carol
-        // orders are added only for "ref_orders"
+        // orders are added for "orders" and "ref_orders"
         for count in 1..=9 {
             if count <= 3 {
-                let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\"}".to_string();
-                contract.add_order_from_string_2(alice(), order_alice);
+                let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+                set_predecessor(alice());
+                contract.add_order_from_string_2(order_alice);
             } else if count <= 6 {
-                let order_bob = "{\"account_id\":\"bob.near\",\"amount\":\"300\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\"}".to_string();
-                contract.add_order_from_string_2(bob(), order_bob);
+                let order_bob = "{\"account_id\":\"bob.near\",\"amount\":\"300\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+                set_predecessor(bob());
+                contract.add_order_from_string_2(order_bob);
             } else {
-                let order_carol = "{\"account_id\":\"bob.near\",\"amount\":\"500\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\"}".to_string();
-                contract.add_order_from_string_2(carol(), order_carol);
+                let order_carol = "{\"account_id\":\"carol.near\",\"amount\":\"500\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+                set_predecessor(carol());
+                contract.add_order_from_string_2(order_carol);
             }
         }
         // Orders for the "wnear.near|usdt.near" pair from users: Alice, Bob, Carol
-        // orders are added only for "ref_orders"
+        // asking more than the bids above, so they rest in the same book
+        // orders are added for "orders" and "ref_orders"
         for count in 1..=9 {
             if count <= 3 {
-                let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"wnear.near\",\"buy_token\":\"usdt.near\"}".to_string();
-                contract.add_order_from_string_2(alice(), order_alice);
+                let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"wnear.near\",\"buy_token\":\"usdt.near\",\"price\":\"2\",\"side\":\"sell\"}".to_string();
+                set_predecessor(alice());
+                contract.add_order_from_string_2(order_alice);
             } else if count <= 6 {
-                let order_bob = "{\"account_id\":\"bob.near\",\"amount\":\"300\",\"sell_token\":\"wnear.near\",\"buy_token\":\"usdt.near\"}".to_string();
-                contract.add_order_from_string_2(bob(), order_bob);
+                let order_bob = "{\"account_id\":\"bob.near\",\"amount\":\"300\",\"sell_token\":\"wnear.near\",\"buy_token\":\"usdt.near\",\"price\":\"2\",\"side\":\"sell\"}".to_string();
+                set_predecessor(bob());
+                contract.add_order_from_string_2(order_bob);
             } else {
-                let order_carol = "{\"account_id\":\"bob.near\",\"amount\":\"500\",\"sell_token\":\"wnear.near\",\"buy_token\":\"usdt.near\"}".to_string();
-                contract.add_order_from_string_2(carol(), order_carol);
+                let order_carol = "{\"account_id\":\"carol.near\",\"amount\":\"500\",\"sell_token\":\"wnear.near\",\"buy_token\":\"usdt.near\",\"price\":\"2\",\"side\":\"sell\"}".to_string();
+                set_predecessor(carol());
+                contract.add_order_from_string_2(order_carol);
             }
         }
 
-        // Must be "0"
+        // Must be "3"
         println!("Number of users after- {}", contract.orders.len());
-        // Must be "2"
+        // Must be "1"
         println!("Number of pairs afters - {}", contract.ref_orders.len());
         println!("-------------------");
 
         // Must be "9"
         println!(
             "Number of orders from usdt.near|wnear.near - {}",
-            contract.ref_orders.get(&pair_id_one.clone()).unwrap().len()
+            contract
+                .view_ref_orders(pair_id_one.clone(), None, None, None)
+                .len()
         );
         // Must be "9"
         println!(
             "Number of orders from wnear.near|usdt.near - {}",
-            contract.ref_orders.get(&pair_id_two.clone()).unwrap().len()
+            contract
+                .view_ref_orders(pair_id_two.clone(), None, None, None)
+                .len()
         );
         println!("-------------------");
         // Must be "9" (#1...#9)
-        println!("{:#?}", contract.view_ref_orders(&pair_id_one));
+        println!(
+            "{:#?}",
+            contract.view_ref_orders(pair_id_one.clone(), None, None, None)
+        );
         println!("-------------------");
         // Must be "9" (#10...#18)
-        println!("{:#?}", contract.view_ref_orders(&pair_id_two));
+        println!(
+            "{:#?}",
+            contract.view_ref_orders(pair_id_two.clone(), None, None, None)
+        );
     }
 
-    // Test for adding to the fields "orders" and "ref_orders"
+    // Test for adding through "add_order_from_string_3"
     // Three users: Alice, Bob, Carol
-    // Two pairs: "usdt.near|wnear.near" and "wnear.near|usdt.near"
+    // Both directions of one market: "usdt.near|wnear.near" and "wnear.near|usdt.near"
     #[test]
     fn test_7() {
         let mut contract = Contract::new();
+        fund_accounts(&mut contract);
 
         let pair_id_one: PairId = ("usdt.near".parse().unwrap(), "wnear.near".parse().unwrap());
         let pair_id_two: PairId = ("wnear.near".parse().unwrap(), "usdt.near".parse().unwrap());
@@ -583,35 +967,42 @@ mod tests {
         // orders are added for "orders" and "ref_orders"
         for count in 1..=9 {
             if count <= 3 {
-                let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\"}".to_string();
-                contract.add_order_from_string_3(alice(), order_alice);
+                let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+                set_predecessor(alice());
+                contract.add_order_from_string_3(order_alice);
             } else if count <= 6 {
-                let order_bob = "{\"account_id\":\"bob.near\",\"amount\":\"300\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\"}".to_string();
-                contract.add_order_from_string_3(bob(), order_bob);
+                let order_bob = "{\"account_id\":\"bob.near\",\"amount\":\"300\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+                set_predecessor(bob());
+                contract.add_order_from_string_3(order_bob);
             } else {
-                let order_carol = "{\"account_id\":\"bob.near\",\"amount\":\"500\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\"}".to_string();
-                contract.add_order_from_string_3(carol(), order_carol);
+                let order_carol = "{\"account_id\":\"carol.near\",\"amount\":\"500\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+                set_predecessor(carol());
+                contract.add_order_from_string_3(order_carol);
             }
         }
 
         // Orders for the "wnear.near|usdt.near" pair from users: Alice, Bob, Carol
+        // asking more than the bids above, so they rest in the same book
         // orders are added for "orders" and "ref_orders"
         for count in 1..=9 {
             if count <= 3 {
-                let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"wnear.near\",\"buy_token\":\"usdt.near\"}".to_string();
-                contract.add_order_from_string_3(alice(), order_alice);
+                let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"wnear.near\",\"buy_token\":\"usdt.near\",\"price\":\"2\",\"side\":\"sell\"}".to_string();
+                set_predecessor(alice());
+                contract.add_order_from_string_3(order_alice);
             } else if count <= 6 {
-                let order_bob = "{\"account_id\":\"bob.near\",\"amount\":\"300\",\"sell_token\":\"wnear.near\",\"buy_token\":\"usdt.near\"}".to_string();
-                contract.add_order_from_string_3(bob(), order_bob);
+                let order_bob = "{\"account_id\":\"bob.near\",\"amount\":\"300\",\"sell_token\":\"wnear.near\",\"buy_token\":\"usdt.near\",\"price\":\"2\",\"side\":\"sell\"}".to_string();
+                set_predecessor(bob());
+                contract.add_order_from_string_3(order_bob);
             } else {
-                let order_carol = "{\"account_id\":\"bob.near\",\"amount\":\"500\",\"sell_token\":\"wnear.near\",\"buy_token\":\"usdt.near\"}".to_string();
-                contract.add_order_from_string_3(carol(), order_carol);
+                let order_carol = "{\"account_id\":\"carol.near\",\"amount\":\"500\",\"sell_token\":\"wnear.near\",\"buy_token\":\"usdt.near\",\"price\":\"2\",\"side\":\"sell\"}".to_string();
+                set_predecessor(carol());
+                contract.add_order_from_string_3(order_carol);
             }
         }
 
         // Must be "3"
         println!("Number of users after- {}", contract.orders.len());
-        // Must be "2"
+        // Must be "1"
         println!("Number of pairs afters - {}", contract.ref_orders.len());
         println!("-------------------");
 
@@ -635,28 +1026,248 @@ mod tests {
         // Must be "9"
         println!(
             "Number of orders from usdt.near|wnear.near - {}",
-            contract.ref_orders.get(&pair_id_one.clone()).unwrap().len()
+            contract
+                .view_ref_orders(pair_id_one.clone(), None, None, None)
+                .len()
         );
         // Must be "9"
         println!(
             "Number of orders from wnear.near|usdt.near - {}",
-            contract.ref_orders.get(&pair_id_two.clone()).unwrap().len()
+            contract
+                .view_ref_orders(pair_id_two.clone(), None, None, None)
+                .len()
         );
         println!("-------------------");
         // Must be "6" (#1...#3, #10...#12)
-        println!("{:#?}", contract.view_orders(&alice()));
+        println!("{:#?}", contract.view_orders(alice(), None, None, None));
         println!("-------------------");
         // Must be "6" (#4...#6, #13...#15)
-        println!("{:#?}", contract.view_orders(&bob()));
+        println!("{:#?}", contract.view_orders(bob(), None, None, None));
         println!("-------------------");
         // Must be "6" (#7...#9, #16...#18)
-        println!("{:#?}", contract.view_orders(&carol()));
+        println!("{:#?}", contract.view_orders(carol(), None, None, None));
         println!("-------------------");
-        
+
         // Must be "9" (#1...#9)
-        println!("{:#?}", contract.view_ref_orders(&pair_id_one));
+        println!(
+            "{:#?}",
+            contract.view_ref_orders(pair_id_one.clone(), None, None, None)
+        );
         println!("-------------------");
         // Must be "9" (#10...#18)
-        println!("{:#?}", contract.view_ref_orders(&pair_id_two));
+        println!(
+            "{:#?}",
+            contract.view_ref_orders(pair_id_two.clone(), None, None, None)
+        );
+    }
+
+    // Orders written to "ref_orders" must not show up in "orders" and vice versa
+    #[test]
+    fn test_8() {
+        let mut contract = Contract::new_1();
+        fund_accounts(&mut contract);
+
+        let pair_id: PairId = ("usdt.near".parse().unwrap(), "wnear.near".parse().unwrap());
+        let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+        set_predecessor(alice());
+        contract.add_order_from_string_2(order_alice);
+
+        assert_eq!(contract.orders.len(), 1);
+        assert_eq!(contract.orders.get(&alice()).unwrap().len(), 4);
+        assert_eq!(contract.ref_orders.len(), 1);
+        assert_eq!(
+            contract
+                .view_ref_orders(pair_id.clone(), None, None, None)
+                .len(),
+            1
+        );
+
+        let order_bob = "{\"account_id\":\"bob.near\",\"amount\":\"100\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+        set_predecessor(bob());
+        contract.add_order_from_string_1(order_bob);
+
+        assert_eq!(contract.orders.len(), 2);
+        assert_eq!(
+            contract
+                .view_ref_orders(pair_id.clone(), None, None, None)
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "overlap")]
+    fn test_overlapping_prefixes() {
+        assert_disjoint_prefixes(&[
+            StorageKey::Orders.into_storage_key(),
+            StorageKey::RefOrders.into_storage_key(),
+            b"\x00".to_vec(),
+        ]);
+    }
+
+    // Gas of a single insert with the nested layout against the old
+    // "UnorderedMap<AccountId, HashMap<u64, Order>>" layout
+    #[test]
+    fn test_insert_gas() {
+        use std::collections::HashMap;
+
+        fn order() -> Order {
+            Order {
+                account_id: alice(),
+                amount: BigDecimal::from(100),
+                sell_token: "usdt.near".parse().unwrap(),
+                buy_token: "wnear.near".parse().unwrap(),
+                price: BigDecimal::from(1),
+                side: Side::Sell,
+                time_in_force: TimeInForce::Gtc,
+                status: OrderStatus::Open,
+                filled_amount: BigDecimal::zero(),
+                created_at: U64(0),
+                expires_at: None,
+            }
+        }
+
+        fn measure(f: impl FnOnce()) -> u64 {
+            let before = env::used_gas().0;
+            f();
+            env::used_gas().0 - before
+        }
+
+        // The old layout gets its own prefix, the storage is shared by both runs
+        set_predecessor(alice());
+        let mut legacy = UnorderedMap::<AccountId, HashMap<u64, Order>>::new(b"legacy".to_vec());
+        let mut legacy_gas = vec![];
+        for order_id in 1..=100u64 {
+            legacy_gas.push(measure(|| {
+                let mut orders = legacy.get(&alice()).unwrap_or_default();
+                orders.insert(order_id, order());
+                legacy.insert(&alice(), &orders);
+            }));
+        }
+
+        set_predecessor(alice());
+        let mut contract = Contract::new();
+        fund_accounts(&mut contract);
+        let mut nested_gas = vec![];
+        for order_id in 1..=100u64 {
+            nested_gas.push(measure(|| {
+                contract.insert_order(&alice(), order(), order_id)
+            }));
+        }
+
+        println!(
+            "Legacy layout: 2nd insert - {}, 100th insert - {}",
+            legacy_gas[1], legacy_gas[99]
+        );
+        println!(
+            "Nested layout: 2nd insert - {}, 100th insert - {}",
+            nested_gas[1], nested_gas[99]
+        );
+
+        assert!(legacy_gas[99] > legacy_gas[1] * 2);
+        assert!(nested_gas[99] < legacy_gas[99]);
+        assert!(nested_gas[99] <= nested_gas[1] * 11 / 10);
+    }
+
+    // An order can be found by its id only
+    #[test]
+    fn test_get_order() {
+        let mut contract = Contract::new();
+        fund_accounts(&mut contract);
+
+        let order_bob = "{\"account_id\":\"bob.near\",\"amount\":\"300\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+        set_predecessor(bob());
+        contract.add_order_from_string_1(order_bob.clone());
+        let order_id = contract.add_order_from_string_3(order_bob);
+
+        assert_eq!(order_id, U64(2));
+        let order = contract.get_order(order_id).unwrap();
+        assert_eq!(order.amount, BigDecimal::from(300));
+        assert_eq!(
+            contract.order_index.get(&order_id.0).unwrap(),
+            (
+                bob(),
+                ("wnear.near".parse().unwrap(), "usdt.near".parse().unwrap())
+            )
+        );
+        assert!(contract.get_order(U64(3)).is_none());
+    }
+
+    // Cancelling every order releases all the storage taken by them,
+    // when the history doesn't keep them
+    #[test]
+    fn test_cancel_order() {
+        let mut contract = Contract::new();
+        contract.set_history_retention(0);
+        fund_accounts(&mut contract);
+
+        let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+        set_predecessor(alice());
+        let storage_usage = env::storage_usage();
+        let first = contract.add_order_from_string_3(order_alice.clone());
+        let second = contract.add_order_from_string_3(order_alice);
+
+        let order = contract.cancel_order(first);
+        assert_eq!(order.amount, BigDecimal::from(100));
+        assert!(contract.get_order(first).is_none());
+        assert_eq!(contract.view_orders(alice(), None, None, None).len(), 1);
+        assert_eq!(contract.ref_orders.len(), 1);
+
+        contract.cancel_order(second);
+        assert_eq!(contract.orders.len(), 0);
+        assert_eq!(contract.ref_orders.len(), 0);
+        assert_eq!(env::storage_usage(), storage_usage);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can cancel the order")]
+    fn test_cancel_order_not_owner() {
+        let mut contract = Contract::new();
+        fund_accounts(&mut contract);
+
+        let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"100\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+        set_predecessor(alice());
+        let order_id = contract.add_order_from_string_3(order_alice);
+
+        set_predecessor(bob());
+        contract.cancel_order(order_id);
+    }
+
+    #[test]
+    fn test_add_invalid_order() {
+        let mut contract = Contract::new();
+        fund_accounts(&mut contract);
+
+        let order_alice = "{\"account_id\":\"alice.near\",\"amount\":\"1O0\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+        set_predecessor(alice());
+        assert_eq!(
+            contract.add_order_from_string(order_alice),
+            Err(OrderError::InvalidAmount("1O0".to_string()))
+        );
+        assert!(contract.view_orders(alice(), None, None, None).is_empty());
+        assert_eq!(contract.order_nonce, 0);
+    }
+
+    #[test]
+    fn test_add_order_for_another_account() {
+        let mut contract = Contract::new();
+        fund_accounts(&mut contract);
+
+        // Carol submits an order that claims to be Bob's
+        let order_carol = "{\"account_id\":\"bob.near\",\"amount\":\"500\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"1\",\"side\":\"sell\"}".to_string();
+        set_predecessor(carol());
+        assert_eq!(
+            contract.add_order_from_string(order_carol),
+            Err(OrderError::AccountMismatch(bob()))
+        );
+        assert!(contract.view_orders(bob(), None, None, None).is_empty());
+        assert!(contract.view_orders(carol(), None, None, None).is_empty());
+        assert_eq!(
+            contract.get_balance(carol(), "usdt.near".parse().unwrap()),
+            Balance {
+                available: BigDecimal::from(1_000_000u128),
+                locked: BigDecimal::zero(),
+            }
+        );
     }
 }
@@ -0,0 +1,253 @@
+use crate::fees::fee_of;
+use crate::*;
+
+/// An order filled at once against the book, at whatever prices the book has.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MarketOrder {
+    /// (base, quote) of the book
+    pub pair_id: PairId,
+    pub side: Side,
+    /// Amount of the base token to fill
+    pub amount: BigDecimal,
+    /// Least amount to receive after the fee: the base token for a buy,
+    /// the quote token for a sell
+    pub min_receive: Option<BigDecimal>,
+    /// Highest average price of a buy
+    pub max_price: Option<BigDecimal>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MarketOrderResult {
+    pub order_id: U64,
+    /// Amount of the base token filled, less than the order's amount
+    /// if the book doesn't have enough
+    pub filled_amount: BigDecimal,
+    /// Amount of the quote token of the fills divided by the filled amount
+    pub average_price: BigDecimal,
+    /// Received after the fee: the base token for a buy, the quote token for a sell
+    pub total_received: BigDecimal,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Sweeps the book with the available balance of the caller.
+    /// The whole call is reverted if the fills would breach "min_receive" or "max_price".
+    pub fn market_order(&mut self, order: MarketOrder) -> MarketOrderResult {
+        self.internal_market_order(&env::predecessor_account_id(), order)
+            .unwrap_or_else(|err| env::panic_str(&err.to_string()))
+    }
+}
+
+impl Contract {
+    /// Plans the fills first and checks the guards against them,
+    /// then adds the order as an immediate-or-cancel order at the worst planned price,
+    /// which gets the same fills. A rejected order changes nothing.
+    pub(crate) fn internal_market_order(
+        &mut self,
+        account_id: &AccountId,
+        order: MarketOrder,
+    ) -> Result<MarketOrderResult, OrderError> {
+        self.check_not_paused()?;
+        match (order.side, &order.min_receive, &order.max_price) {
+            (_, None, None) | (Side::Sell, _, Some(_)) => return Err(OrderError::InvalidGuard),
+            _ => {}
+        }
+        let pair = self
+            .pairs
+            .get(&order.pair_id)
+            .ok_or_else(|| OrderError::UnknownPair(order.pair_id.clone()))?;
+        if !pair.enabled {
+            return Err(OrderError::PairDisabled(pair.pair_id()));
+        }
+        let amount = order.amount.floor_to_step(&pair.lot_size);
+        if amount == BigDecimal::zero() {
+            return Err(OrderError::BelowLotSize(pair.lot_size));
+        }
+
+        let fills = self.plan_sweep(&order.pair_id, order.side, amount, |_| true);
+        let worst_price = match fills.last() {
+            Some(fill) => fill.price,
+            None => return Err(OrderError::NotFillable),
+        };
+        let fee_bps = self.get_fees(order.pair_id.clone()).taker_fee_bps;
+        let mut filled_amount = BigDecimal::zero();
+        let mut quote_amount = BigDecimal::zero();
+        let mut total_received = BigDecimal::zero();
+        for fill in &fills {
+            let cost = fill.amount * fill.price;
+            let received = match order.side {
+                Side::Buy => fill.amount,
+                Side::Sell => cost,
+            };
+            filled_amount = filled_amount + fill.amount;
+            quote_amount = quote_amount + cost;
+            total_received = total_received + received - fee_of(received, fee_bps);
+        }
+        let average_price = quote_amount / filled_amount;
+
+        if order
+            .min_receive
+            .is_some_and(|min_receive| total_received < min_receive)
+            || order
+                .max_price
+                .is_some_and(|max_price| average_price > max_price)
+        {
+            return Err(OrderError::SlippageExceeded);
+        }
+
+        let (base, quote) = order.pair_id;
+        let (sell_token, buy_token) = match order.side {
+            Side::Buy => (quote, base),
+            Side::Sell => (base, quote),
+        };
+        let mut limit_order = Order {
+            account_id: account_id.clone(),
+            amount: filled_amount,
+            sell_token,
+            buy_token,
+            price: worst_price,
+            side: order.side,
+            time_in_force: TimeInForce::Ioc,
+            status: OrderStatus::Open,
+            filled_amount: BigDecimal::zero(),
+            created_at: U64(0),
+            expires_at: None,
+        };
+        self.internal_normalize_order(&mut limit_order)?;
+        let order_id = self.internal_add_order(account_id, limit_order)?;
+
+        Ok(MarketOrderResult {
+            order_id: order_id.into(),
+            filled_amount,
+            average_price,
+            total_received,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{add_order, big, register, set_predecessor, usdt, wnear};
+    use near_sdk::test_utils::test_env::{alice, bob, carol};
+
+    /// Asks of 10 at 2 and 10 at 3, a bid of 5 at 1.5.
+    fn prepared_book() -> Contract {
+        let mut contract = Contract::new();
+        add_order(&mut contract, alice(), "sell", "2", "10");
+        add_order(&mut contract, carol(), "sell", "3", "10");
+        add_order(&mut contract, carol(), "buy", "1.5", "5");
+        register(&mut contract, bob());
+        contract.internal_deposit(&bob(), &usdt(), big("100"));
+        contract.internal_deposit(&bob(), &wnear(), big("100"));
+        set_predecessor(bob());
+        contract
+    }
+
+    fn market(
+        side: Side,
+        amount: &str,
+        min_receive: Option<&str>,
+        max_price: Option<&str>,
+    ) -> MarketOrder {
+        MarketOrder {
+            pair_id: (wnear(), usdt()),
+            side,
+            amount: big(amount),
+            min_receive: min_receive.map(big),
+            max_price: max_price.map(big),
+        }
+    }
+
+    #[test]
+    fn test_market_buy_sweeps_the_book() {
+        let mut contract = prepared_book();
+
+        let result = contract
+            .internal_market_order(&bob(), market(Side::Buy, "15", None, Some("2.5")))
+            .unwrap();
+        assert_eq!(result.filled_amount, big("15"));
+        // (10 * 2 + 5 * 3) / 15
+        assert_eq!(result.average_price, big("35") / big("15"));
+        assert_eq!(result.total_received, big("15"));
+
+        assert_eq!(contract.get_balance(bob(), wnear()).available, big("115"));
+        let usdt_balance = contract.get_balance(bob(), usdt());
+        assert_eq!(usdt_balance.available, big("65"));
+        assert_eq!(usdt_balance.locked, BigDecimal::zero());
+        assert_eq!(
+            contract.view_order_history(bob(), None, None)[0].1.status,
+            OrderStatus::Filled
+        );
+    }
+
+    #[test]
+    fn test_market_sell_with_fees() {
+        let mut contract = prepared_book();
+        contract.default_fees = FeeSchedule {
+            maker_fee_bps: 0,
+            taker_fee_bps: 100,
+        };
+
+        // Only 5 can be filled, at 1.5
+        let result = contract
+            .internal_market_order(&bob(), market(Side::Sell, "8", Some("7"), None))
+            .unwrap();
+        assert_eq!(result.filled_amount, big("5"));
+        assert_eq!(result.average_price, big("1.5"));
+        assert_eq!(result.total_received, big("7.425"));
+        assert_eq!(
+            contract.get_balance(bob(), usdt()).available,
+            big("107.425")
+        );
+        assert_eq!(contract.get_balance(bob(), wnear()).available, big("95"));
+    }
+
+    #[test]
+    fn test_slippage_guard_reverts() {
+        let mut contract = prepared_book();
+
+        assert_eq!(
+            contract.internal_market_order(&bob(), market(Side::Buy, "15", None, Some("2.3"))),
+            Err(OrderError::SlippageExceeded)
+        );
+        assert_eq!(
+            contract.internal_market_order(&bob(), market(Side::Sell, "5", Some("7.6"), None)),
+            Err(OrderError::SlippageExceeded)
+        );
+        assert_eq!(contract.order_nonce, 3);
+        assert_eq!(contract.get_balance(bob(), usdt()).available, big("100"));
+        assert_eq!(contract.get_order(U64(1)).unwrap().amount, big("10"));
+    }
+
+    #[test]
+    fn test_market_order_guards_and_book() {
+        let mut contract = prepared_book();
+
+        assert_eq!(
+            contract.internal_market_order(&bob(), market(Side::Buy, "1", None, None)),
+            Err(OrderError::InvalidGuard)
+        );
+        assert_eq!(
+            contract.internal_market_order(&bob(), market(Side::Sell, "1", None, Some("2"))),
+            Err(OrderError::InvalidGuard)
+        );
+
+        let mut order = market(Side::Buy, "1", Some("1"), None);
+        order.pair_id = (usdt(), wnear());
+        assert_eq!(
+            contract.internal_market_order(&bob(), order),
+            Err(OrderError::UnknownPair((usdt(), wnear())))
+        );
+
+        set_predecessor(carol());
+        contract.cancel_order(U64(3));
+        set_predecessor(bob());
+        assert_eq!(
+            contract.internal_market_order(&bob(), market(Side::Sell, "1", Some("1"), None)),
+            Err(OrderError::NotFillable)
+        );
+    }
+}
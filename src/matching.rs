@@ -0,0 +1,261 @@
+use crate::events::{Event, OrderFillEvent};
+use crate::*;
+use near_sdk::StorageUsage;
+
+/// One match of an incoming order against a resting one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fill {
+    pub maker_order_id: u64,
+    /// The maker's price
+    pub price: BigDecimal,
+    /// Amount of the base token
+    pub amount: BigDecimal,
+}
+
+impl Contract {
+    /// The fills the order would get: the opposite side of the order's book
+    /// is walked while the prices cross. Nothing is changed.
+    pub(crate) fn plan_fills(&self, order: &Order) -> Vec<Fill> {
+        self.plan_sweep(&order.book_pair_id(), order.side, order.amount, |price| {
+            order.crosses(price)
+        })
+    }
+
+    /// The fills of `amount` of the base token on the `side` of the (base, quote) book:
+    /// the opposite side is walked from the best price while `accepts` its prices,
+    /// skipping the expired orders. Nothing is changed.
+    pub(crate) fn plan_sweep(
+        &self,
+        pair_id: &PairId,
+        side: Side,
+        amount: BigDecimal,
+        accepts: impl Fn(BigDecimal) -> bool,
+    ) -> Vec<Fill> {
+        let book = match self.order_books.get(pair_id) {
+            Some(book) => book,
+            None => return vec![],
+        };
+
+        let mut fills = vec![];
+        let mut remaining = amount;
+        let timestamp = env::block_timestamp();
+        for (key, entry) in book.iter(side.opposite()) {
+            if remaining == BigDecimal::zero() || !accepts(key.price) {
+                break;
+            }
+            if entry.is_expired(timestamp) {
+                continue;
+            }
+            let amount = remaining.min(entry.amount);
+            remaining = remaining - amount;
+            fills.push(Fill {
+                maker_order_id: entry.order_id,
+                price: key.price,
+                amount,
+            });
+        }
+        fills
+    }
+
+    /// Makes the fills planned for the order.
+    /// Every fill is made at the maker's price and recorded as a trade,
+    /// `order.amount` is left with the unfilled remainder
+    /// and `order.status` tells whether anything is left.
+    /// Also returns the storage released by the filled maker orders.
+    pub(crate) fn match_order(
+        &mut self,
+        account_id: &AccountId,
+        order_id: u64,
+        order: &mut Order,
+    ) -> (Vec<Fill>, StorageUsage) {
+        let fills = self.plan_fills(order);
+        let remaining = order.amount
+            - fills
+                .iter()
+                .fold(BigDecimal::zero(), |sum, fill| sum + fill.amount);
+
+        let pair_id = order.book_pair_id();
+        let mut taker = order.clone();
+        let mut released_storage = 0;
+        for fill in &fills {
+            let initial_storage_usage = env::storage_usage();
+            let (maker_id, maker) = self.fill_maker_order(fill);
+            if fill.amount == maker.amount {
+                released_storage += initial_storage_usage.saturating_sub(env::storage_usage());
+                self.internal_update_storage(&maker_id, initial_storage_usage);
+            }
+
+            let (buyer_id, buyer_price, buyer_remaining, seller_id) = match order.side {
+                Side::Buy => (account_id, order.price, taker.amount, &maker_id),
+                Side::Sell => (&maker_id, maker.price, maker.amount, account_id),
+            };
+            self.settle_fill(
+                &pair_id,
+                fill,
+                order.side,
+                buyer_id,
+                buyer_price,
+                buyer_remaining,
+                seller_id,
+            );
+
+            let trade = Trade {
+                maker_order_id: fill.maker_order_id.into(),
+                taker_order_id: order_id.into(),
+                maker_id,
+                taker_id: account_id.clone(),
+                pair_id: pair_id.clone(),
+                taker_side: order.side,
+                price: fill.price,
+                amount: fill.amount,
+                timestamp: env::block_timestamp().into(),
+            };
+            Event::Trade(vec![trade.clone()]).emit();
+            self.record_trade(trade);
+
+            OrderFillEvent::new(fill.maker_order_id, &maker, fill)
+                .into_event()
+                .emit();
+            OrderFillEvent::new(order_id, &taker, fill)
+                .into_event()
+                .emit();
+            taker.amount = taker.amount - fill.amount;
+        }
+        order.filled_amount = order.filled_amount + (order.amount - remaining);
+        order.amount = remaining;
+        if remaining == BigDecimal::zero() {
+            order.status = OrderStatus::Filled;
+        } else if order.filled_amount > BigDecimal::zero() {
+            order.status = OrderStatus::PartiallyFilled;
+        }
+
+        (fills, released_storage)
+    }
+
+    /// A resting order of the book.
+    fn internal_get_book_order(&self, order_id: u64) -> Order {
+        self.get_order(order_id.into()).expect("Order not found")
+    }
+
+    // A fully filled maker order leaves the book and every index for the history.
+    // Returns the owner and the order as it was before the fill.
+    fn fill_maker_order(&mut self, fill: &Fill) -> (AccountId, Order) {
+        let (account_id, pair_id) = self
+            .order_index
+            .get(&fill.maker_order_id)
+            .expect("Order not found");
+        let maker = self.internal_get_book_order(fill.maker_order_id);
+
+        let mut updated = maker.clone();
+        updated.amount = maker.amount - fill.amount;
+        updated.filled_amount = maker.filled_amount + fill.amount;
+        if fill.amount == maker.amount {
+            self.internal_remove_order(&account_id, &pair_id, fill.maker_order_id);
+            updated.status = OrderStatus::Filled;
+            self.internal_record_closed_order(&account_id, fill.maker_order_id, &updated);
+        } else {
+            updated.status = OrderStatus::PartiallyFilled;
+            self.internal_update_order(&account_id, &pair_id, fill.maker_order_id, updated);
+        }
+        (account_id, maker)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{add_order, big, order_json, set_predecessor, wnear_usdt};
+    use near_sdk::test_utils::test_env::{alice, bob, carol};
+
+    #[test]
+    fn test_no_cross() {
+        let mut contract = Contract::new();
+
+        add_order(&mut contract, alice(), "sell", "2", "10");
+        add_order(&mut contract, bob(), "buy", "1.9", "10");
+
+        let book = contract.view_order_book(wnear_usdt(), 10);
+        assert_eq!(book.asks.len(), 1);
+        assert_eq!(book.bids.len(), 1);
+        assert_eq!(contract.view_orders(alice(), None, None, None).len(), 1);
+        assert_eq!(contract.view_orders(bob(), None, None, None).len(), 1);
+    }
+
+    #[test]
+    fn test_partial_fill_of_maker() {
+        let mut contract = Contract::new();
+
+        let maker_id = add_order(&mut contract, alice(), "sell", "2", "10");
+        let taker_id = add_order(&mut contract, bob(), "buy", "2.5", "4");
+
+        // The taker is filled completely and never rests on the book
+        assert!(contract.get_order(taker_id).is_none());
+        assert!(contract.view_orders(bob(), None, None, None).is_empty());
+
+        let maker = contract.get_order(maker_id).unwrap();
+        assert_eq!(maker.amount, big("6"));
+        assert_eq!(
+            contract.view_ref_orders(
+                ("wnear.near".parse().unwrap(), "usdt.near".parse().unwrap()),
+                None,
+                None,
+                None
+            )[0]
+            .1
+            .amount,
+            big("6")
+        );
+        let book = contract.view_order_book(wnear_usdt(), 10);
+        assert_eq!(book.asks[0].amount, big("6"));
+        assert!(book.bids.is_empty());
+    }
+
+    #[test]
+    fn test_sweep_levels_and_rest_remainder() {
+        let mut contract = Contract::new();
+
+        let first = add_order(&mut contract, alice(), "sell", "2", "3");
+        let second = add_order(&mut contract, carol(), "sell", "2.1", "2");
+        let out_of_price = add_order(&mut contract, carol(), "sell", "3", "5");
+
+        let mut taker: Order =
+            near_sdk::serde_json::from_str(&order_json(&bob(), "buy", "2.2", "7")).unwrap();
+        contract.internal_deposit(&bob(), &taker.sell_token, taker.sell_amount());
+        contract
+            .internal_lock(&bob(), &taker.sell_token, taker.sell_amount())
+            .unwrap();
+        set_predecessor(bob());
+        let (fills, _) = contract.match_order(&bob(), 100, &mut taker);
+
+        assert_eq!(
+            fills,
+            vec![
+                Fill {
+                    maker_order_id: first.0,
+                    price: big("2"),
+                    amount: big("3"),
+                },
+                Fill {
+                    maker_order_id: second.0,
+                    price: big("2.1"),
+                    amount: big("2"),
+                },
+            ]
+        );
+        assert_eq!(taker.amount, big("2"));
+        assert!(contract.get_order(first).is_none());
+        assert!(contract.get_order(second).is_none());
+        assert!(contract.get_order(out_of_price).is_some());
+        assert!(contract.view_orders(alice(), None, None, None).is_empty());
+        assert_eq!(contract.view_orders(carol(), None, None, None).len(), 1);
+
+        // Through the entry point the remainder rests on the bid side at the taker's price
+        add_order(&mut contract, alice(), "sell", "2", "1");
+        let taker_id = add_order(&mut contract, bob(), "buy", "2.2", "3");
+        assert_eq!(contract.get_order(taker_id).unwrap().amount, big("2"));
+        let book = contract.view_order_book(wnear_usdt(), 10);
+        assert_eq!(book.bids[0].price, big("2.2"));
+        assert_eq!(book.bids[0].amount, big("2"));
+        assert_eq!(book.asks.len(), 1);
+    }
+}
@@ -0,0 +1,304 @@
+//! Upgrades of the contract state.
+//!
+//! `Contract` is stored with Borsh under the "STATE" key, so a new field
+//! can't be read from a state written by an older version of the code.
+//! Every layout that has been deployed is kept here as a variant of
+//! `VersionedContract`, and `migrate` turns any of them into the current one.
+//! The version of the layout is stored under the "VERSION" key next to the state,
+//! so two layouts with the same bytes can't be mistaken for each other.
+//!
+//! To change the layout of `Contract`:
+//! 1. copy the deployed `Contract` struct here as the next `ContractV<N>`,
+//!    with the types of its fields as they were deployed;
+//! 2. add a `V<N>` variant with the current `STATE_VERSION`, read it in
+//!    `VersionedContract::read` and convert it in `VersionedContract::into_current`;
+//! 3. increase `STATE_VERSION`;
+//! 4. deploy the new code and call `migrate` in the same batch transaction,
+//!    e.g. `near deploy <contract> <wasm> --initFunction migrate --initArgs '{}'`.
+//!
+//! Calling `migrate` on a state that is already current doesn't change it.
+use crate::events::{Event, OrderEvent};
+use crate::*;
+use std::collections::{BTreeMap, HashMap};
+
+const STATE_KEY: &[u8] = b"STATE";
+const VERSION_KEY: &[u8] = b"VERSION";
+/// Prefix of both "orders" and "ref_orders" of V1
+const V1_PREFIX: &[u8] = b"m";
+/// Version of the current layout, the one written by `new` and `migrate`
+pub const STATE_VERSION: u8 = 2;
+
+/// Tags the state with the current layout.
+pub(crate) fn write_state_version() {
+    env::storage_write(VERSION_KEY, &STATE_VERSION.try_to_vec().unwrap());
+}
+
+fn read_state_version() -> Option<u8> {
+    env::storage_read(VERSION_KEY).map(|version| {
+        u8::try_from_slice(&version)
+            .unwrap_or_else(|_| env::panic_str("The state version is invalid"))
+    })
+}
+
+fn deserialize<T: BorshDeserialize>(state: &[u8], version: u8) -> T {
+    T::try_from_slice(state).unwrap_or_else(|_| {
+        env::panic_str(&format!(
+            "The state doesn't match the layout of version {}",
+            version
+        ))
+    })
+}
+
+/// An order of V1: no price and no side, and no tokens locked for it.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug)]
+pub struct OrderV1 {
+    pub account_id: AccountId,
+    pub amount: BigDecimal,
+    pub sell_token: AccountId,
+    pub buy_token: AccountId,
+}
+
+/// The first deployed layout: only the orders, per account and per pair.
+/// The orders of an account or a pair are a `HashMap` stored as one value of the outer map.
+/// Both maps were built with `V1_PREFIX`, so they share their storage.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ContractV1 {
+    pub order_nonce: u64,
+    pub orders: UnorderedMap<AccountId, HashMap<u64, OrderV1>>,
+    pub ref_orders: UnorderedMap<PairId, HashMap<u64, OrderV1>>,
+}
+
+/// Every layout of the contract state, oldest first, with their versions.
+pub enum VersionedContract {
+    /// 1
+    V1(ContractV1),
+    /// `STATE_VERSION`
+    Current(Box<Contract>),
+}
+
+impl VersionedContract {
+    /// Reads the state with the layout of its version.
+    pub fn read() -> Self {
+        let state = env::storage_read(STATE_KEY)
+            .unwrap_or_else(|| env::panic_str("The contract is not initialized"));
+
+        match read_state_version() {
+            Some(1) => VersionedContract::V1(deserialize(&state, 1)),
+            Some(STATE_VERSION) => {
+                VersionedContract::Current(Box::new(deserialize(&state, STATE_VERSION)))
+            }
+            Some(version) => env::panic_str(&format!("The state version {} is unknown", version)),
+            None => Self::read_untagged(&state),
+        }
+    }
+
+    /// The states written before the version was stored: each layout is tried
+    /// from the newest one, Borsh only accepts a layout that reads all the bytes.
+    fn read_untagged(state: &[u8]) -> Self {
+        if let Ok(contract) = Contract::try_from_slice(state) {
+            VersionedContract::Current(Box::new(contract))
+        } else if let Ok(contract) = ContractV1::try_from_slice(state) {
+            VersionedContract::V1(contract)
+        } else {
+            env::panic_str("The state doesn't match any known layout")
+        }
+    }
+
+    pub fn into_current(self) -> Contract {
+        match self {
+            VersionedContract::V1(contract) => contract.into_current(),
+            VersionedContract::Current(contract) => *contract,
+        }
+    }
+}
+
+impl OrderV1 {
+    /// A V1 order sells `amount` of its sell token at no price,
+    /// so it is logged as a sell of the (sell_token, buy_token) pair at a zero price.
+    fn cancelled_event(&self, order_id: u64) -> OrderEvent {
+        OrderEvent {
+            order_id: order_id.into(),
+            account_id: self.account_id.clone(),
+            pair_id: (self.sell_token.clone(), self.buy_token.clone()),
+            side: Side::Sell,
+            price: BigDecimal::zero(),
+            amount: self.amount,
+        }
+    }
+}
+
+/// Storage key of an entry of the V1 maps: `kind` is b'i' for the index of a key,
+/// b'k' for a key and b'v' for a value, see `UnorderedMap`.
+fn v1_key(kind: u8, suffix: &[u8]) -> Vec<u8> {
+    [V1_PREFIX, &[kind], suffix].concat()
+}
+
+impl ContractV1 {
+    /// The orders of V1 have no price and no tokens behind them,
+    /// so they can't be put on a book: they are removed, and "order_cancelled"
+    /// is logged for each one. The nonce is kept, so their ids are never reused.
+    fn into_current(self) -> Contract {
+        for (order_id, order) in self.remove_orders() {
+            Event::OrderCancelled(vec![order.cancelled_event(order_id)]).emit();
+        }
+
+        let mut contract = Contract::from_storage_keys();
+        contract.order_nonce = self.order_nonce;
+        contract
+    }
+
+    /// Removes the storage shared by "orders" and "ref_orders" and returns the orders in it.
+    /// An entry written by one map may have been overwritten by the other one at the same
+    /// position, so the maps can't be cleared one after the other. Both hold
+    /// `HashMap<u64, OrderV1>` values: the entries are read and removed once, as raw bytes,
+    /// up to the length of the longer map.
+    fn remove_orders(&self) -> BTreeMap<u64, OrderV1> {
+        let mut orders = BTreeMap::new();
+        let mut raw_keys = Vec::new();
+        for index in 0..std::cmp::max(self.orders.len(), self.ref_orders.len()) {
+            let index = index.to_le_bytes();
+            if let Some(raw_key) = env::storage_read(&v1_key(b'k', &index)) {
+                env::storage_remove(&v1_key(b'k', &index));
+                raw_keys.push(raw_key);
+            }
+            if let Some(raw_orders) = env::storage_read(&v1_key(b'v', &index)) {
+                env::storage_remove(&v1_key(b'v', &index));
+                if let Ok(map_orders) = HashMap::<u64, OrderV1>::try_from_slice(&raw_orders) {
+                    orders.extend(map_orders);
+                }
+            }
+        }
+        // The index of an overwritten key is only found from the orders stored under it
+        for order in orders.values() {
+            raw_keys.push(order.account_id.try_to_vec().unwrap());
+            raw_keys.push(
+                (order.sell_token.clone(), order.buy_token.clone())
+                    .try_to_vec()
+                    .unwrap(),
+            );
+        }
+        for raw_key in raw_keys {
+            env::storage_remove(&v1_key(b'i', &raw_key));
+        }
+        orders
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Rewrites the state in the current layout and tags it with `STATE_VERSION`,
+    /// see the module docs. The owner of a migrated V1 state is the contract account.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let contract = VersionedContract::read().into_current();
+        write_state_version();
+        contract
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{add_order, register_pair, set_predecessor};
+    use near_sdk::test_utils::get_logs;
+    use near_sdk::test_utils::test_env::{alice, bob};
+    use std::str::FromStr;
+
+    fn order_v1(account_id: AccountId) -> OrderV1 {
+        OrderV1 {
+            account_id,
+            amount: BigDecimal::from_str("1").unwrap(),
+            sell_token: "usdt.near".parse().unwrap(),
+            buy_token: "wnear.near".parse().unwrap(),
+        }
+    }
+
+    /// Writes a V1 state with two orders of alice and one of bob,
+    /// the way `add_order_from_string_3` of V1 did.
+    fn write_v1_state() {
+        let mut contract = ContractV1 {
+            order_nonce: 0,
+            orders: UnorderedMap::new(V1_PREFIX),
+            ref_orders: UnorderedMap::new(V1_PREFIX),
+        };
+        for account_id in [alice(), alice(), bob()] {
+            contract.order_nonce += 1;
+            let order = order_v1(account_id.clone());
+            let mut account_orders = contract.orders.get(&account_id).unwrap_or_default();
+            account_orders.insert(contract.order_nonce, order.clone());
+            contract.orders.insert(&account_id, &account_orders);
+
+            let pair_id = (order.sell_token.clone(), order.buy_token.clone());
+            let mut pair_orders = contract.ref_orders.get(&pair_id).unwrap_or_default();
+            pair_orders.insert(contract.order_nonce, order);
+            contract.ref_orders.insert(&pair_id, &pair_orders);
+        }
+
+        env::state_write(&contract);
+    }
+
+    #[test]
+    fn test_migrate_from_v1() {
+        write_v1_state();
+        assert!(matches!(
+            VersionedContract::read(),
+            VersionedContract::V1(_)
+        ));
+        set_predecessor(env::current_account_id());
+        let mut contract = Contract::migrate();
+
+        assert_eq!(contract.order_nonce, 3);
+        let pair_id: PairId = ("usdt.near".parse().unwrap(), "wnear.near".parse().unwrap());
+        let cancelled: Vec<String> = get_logs()
+            .into_iter()
+            .filter(|log| log.contains("\"event\":\"order_cancelled\""))
+            .collect();
+        assert_eq!(cancelled.len(), 3);
+        assert!(cancelled[2].contains("\"order_id\":\"3\",\"account_id\":\"bob.near\""));
+        // Nothing is left under the prefix of V1
+        for raw_key in [
+            v1_key(b'i', &alice().try_to_vec().unwrap()),
+            v1_key(b'i', &bob().try_to_vec().unwrap()),
+            v1_key(b'i', &pair_id.try_to_vec().unwrap()),
+            v1_key(b'k', &0u64.to_le_bytes()),
+            v1_key(b'v', &0u64.to_le_bytes()),
+            v1_key(b'v', &1u64.to_le_bytes()),
+        ] {
+            assert!(!env::storage_has_key(&raw_key));
+        }
+        assert!(contract.view_orders(alice(), None, None, None).is_empty());
+        assert!(contract.view_orders(bob(), None, None, None).is_empty());
+        assert!(contract
+            .view_ref_orders(pair_id, None, None, None)
+            .is_empty());
+        assert_eq!(contract.get_owner(), env::current_account_id());
+
+        // The migrated state accepts new orders, without reusing the old ids
+        register_pair(&mut contract, "wnear.near", "usdt.near");
+        let order_id = add_order(&mut contract, alice(), "sell", "2", "10");
+        assert_eq!(order_id, U64(4));
+        assert_eq!(contract.view_orders(alice(), None, None, None).len(), 1);
+
+        env::state_write(&contract);
+        assert!(matches!(
+            VersionedContract::read(),
+            VersionedContract::Current(_)
+        ));
+    }
+
+    #[test]
+    fn test_migrate_current_state() {
+        set_predecessor(bob());
+        let mut contract = Contract::new();
+        assert_eq!(read_state_version(), Some(STATE_VERSION));
+        let order_id = add_order(&mut contract, alice(), "sell", "2", "10");
+        env::state_write(&contract);
+
+        let contract = Contract::migrate();
+        assert_eq!(contract.get_owner(), bob());
+        assert_eq!(contract.order_nonce, 1);
+        assert!(contract.get_order(order_id).is_some());
+        assert_eq!(contract.list_pairs(None, None).len(), 1);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::*;
+use near_sdk::collections::TreeMap;
+
+#[derive(
+    BorshDeserialize,
+    BorshSerialize,
+    Serialize,
+    Deserialize,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    /// Buys the base token, which is "buy_token" of the order
+    Buy,
+    /// Sells the base token, which is "sell_token" of the order
+    #[default]
+    Sell,
+}
+
+impl Side {
+    pub fn opposite(&self) -> Self {
+        match self {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
+}
+
+/// Position of an order inside one side of the book.
+/// Bids keep an inverted order id, so iterating them backwards gives the
+/// highest price first and the oldest order first within a price level.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BookKey {
+    pub price: BigDecimal,
+    seq: u64,
+}
+
+impl BookKey {
+    pub fn new(side: Side, price: BigDecimal, order_id: u64) -> Self {
+        let seq = match side {
+            Side::Buy => u64::MAX - order_id,
+            Side::Sell => order_id,
+        };
+        Self { price, seq }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug)]
+pub struct BookEntry {
+    pub order_id: u64,
+    /// Amount of the base token left in the order
+    pub amount: BigDecimal,
+    /// Copied from the order, so the matching skips an expired order
+    /// without reading it
+    pub expires_at: Option<U64>,
+}
+
+impl BookEntry {
+    pub fn is_expired(&self, timestamp: u64) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at.0 <= timestamp)
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Debug)]
+pub struct OrderBook {
+    bids: TreeMap<BookKey, BookEntry>,
+    asks: TreeMap<BookKey, BookEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceLevel {
+    pub price: BigDecimal,
+    pub amount: BigDecimal,
+    pub orders: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OrderBookView {
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+}
+
+impl OrderBook {
+    pub fn new(pair_id: &PairId) -> Self {
+        let pair_hash = env::sha256_array(&pair_id.try_to_vec().unwrap());
+        Self {
+            bids: TreeMap::new(StorageKey::Bids { pair_hash }),
+            asks: TreeMap::new(StorageKey::Asks { pair_hash }),
+        }
+    }
+
+    fn side_mut(&mut self, side: Side) -> &mut TreeMap<BookKey, BookEntry> {
+        match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bids.len() == 0 && self.asks.len() == 0
+    }
+
+    pub fn insert(&mut self, order_id: u64, order: &Order) {
+        let key = BookKey::new(order.side, order.price, order_id);
+        let entry = BookEntry {
+            order_id,
+            amount: order.amount,
+            expires_at: order.expires_at,
+        };
+        self.side_mut(order.side).insert(&key, &entry);
+    }
+
+    pub fn remove(&mut self, order_id: u64, order: &Order) -> Option<BookEntry> {
+        let key = BookKey::new(order.side, order.price, order_id);
+        self.side_mut(order.side).remove(&key)
+    }
+
+    /// Orders of one side in price-time priority, the best price first.
+    pub fn iter(&self, side: Side) -> Box<dyn Iterator<Item = (BookKey, BookEntry)> + '_> {
+        match side {
+            Side::Buy => Box::new(self.bids.iter_rev()),
+            Side::Sell => Box::new(self.asks.iter()),
+        }
+    }
+
+    /// Up to `depth` price levels of one side, the best price first.
+    pub fn levels(&self, side: Side, depth: usize) -> Vec<PriceLevel> {
+        let mut levels: Vec<PriceLevel> = Vec::new();
+        for (key, entry) in self.iter(side) {
+            match levels.last_mut() {
+                Some(level) if level.price == key.price => {
+                    level.amount = level.amount + entry.amount;
+                    level.orders += 1;
+                }
+                _ => {
+                    if levels.len() == depth {
+                        break;
+                    }
+                    levels.push(PriceLevel {
+                        price: key.price,
+                        amount: entry.amount,
+                        orders: 1,
+                    });
+                }
+            }
+        }
+        levels
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Aggregated price levels of the book, `depth` levels per side.
+    pub fn view_order_book(&self, pair_id: PairId, depth: u32) -> OrderBookView {
+        match self.order_books.get(&pair_id) {
+            Some(book) => OrderBookView {
+                bids: book.levels(Side::Buy, depth as usize),
+                asks: book.levels(Side::Sell, depth as usize),
+            },
+            None => OrderBookView {
+                bids: vec![],
+                asks: vec![],
+            },
+        }
+    }
+}
+
+impl Contract {
+    pub(crate) fn insert_book_order(&mut self, order_id: u64, order: &Order) {
+        let pair_id = order.book_pair_id();
+        let mut book = self
+            .order_books
+            .get(&pair_id)
+            .unwrap_or_else(|| OrderBook::new(&pair_id));
+
+        book.insert(order_id, order);
+        self.order_books.insert(&pair_id, &book);
+    }
+
+    // An empty book is removed so its storage is released
+    pub(crate) fn remove_book_order(&mut self, order_id: u64, order: &Order) {
+        let pair_id = order.book_pair_id();
+        let mut book = self
+            .order_books
+            .get(&pair_id)
+            .expect("Order book not found");
+
+        book.remove(order_id, order);
+        if book.is_empty() {
+            self.order_books.remove(&pair_id);
+        } else {
+            self.order_books.insert(&pair_id, &book);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::add_order;
+    use near_sdk::test_utils::test_env::{alice, bob};
+    use std::str::FromStr;
+
+    fn level(price: &str, amount: &str, orders: u32) -> PriceLevel {
+        PriceLevel {
+            price: BigDecimal::from_str(price).unwrap(),
+            amount: BigDecimal::from_str(amount).unwrap(),
+            orders,
+        }
+    }
+
+    #[test]
+    fn test_order_book_levels() {
+        let mut contract = Contract::new();
+        let pair_id: PairId = ("wnear.near".parse().unwrap(), "usdt.near".parse().unwrap());
+
+        add_order(&mut contract, alice(), "buy", "1.5", "10");
+        add_order(&mut contract, bob(), "buy", "1.7", "5");
+        add_order(&mut contract, bob(), "buy", "1.5", "20");
+        add_order(&mut contract, alice(), "sell", "2.1", "7");
+        add_order(&mut contract, alice(), "sell", "1.9", "3");
+        add_order(&mut contract, bob(), "sell", "2.1", "1");
+        add_order(&mut contract, bob(), "sell", "2.5", "1");
+
+        let book = contract.view_order_book(pair_id.clone(), 2);
+        assert_eq!(book.bids, vec![level("1.7", "5", 1), level("1.5", "30", 2)]);
+        assert_eq!(book.asks, vec![level("1.9", "3", 1), level("2.1", "8", 2)]);
+
+        let book = contract.view_order_book(pair_id, 10);
+        assert_eq!(book.asks.len(), 3);
+    }
+
+    #[test]
+    fn test_order_book_time_priority() {
+        let mut contract = Contract::new();
+        let pair_id: PairId = ("wnear.near".parse().unwrap(), "usdt.near".parse().unwrap());
+
+        let first = add_order(&mut contract, alice(), "buy", "1.5", "10");
+        let second = add_order(&mut contract, bob(), "buy", "1.5", "20");
+        let best = add_order(&mut contract, bob(), "buy", "1.6", "1");
+
+        let book = contract.order_books.get(&pair_id).unwrap();
+        let bids = book
+            .iter(Side::Buy)
+            .map(|(_, entry)| U64(entry.order_id))
+            .collect::<Vec<_>>();
+        assert_eq!(bids, vec![best, first, second]);
+    }
+
+    #[test]
+    fn test_order_book_cancel() {
+        let mut contract = Contract::new();
+        let pair_id: PairId = ("wnear.near".parse().unwrap(), "usdt.near".parse().unwrap());
+
+        let order_id = add_order(&mut contract, alice(), "sell", "2", "10");
+        assert_eq!(contract.view_order_book(pair_id.clone(), 10).asks.len(), 1);
+
+        contract.cancel_order(order_id);
+        assert!(contract
+            .view_order_book(pair_id.clone(), 10)
+            .asks
+            .is_empty());
+        assert!(contract.order_books.get(&pair_id).is_none());
+    }
+}
@@ -0,0 +1,184 @@
+use crate::*;
+
+/// Open orders of one account or one pair, linked in the order of their ids.
+/// The ids come from "order_nonce", so a new order is always linked after the last one:
+/// adding or removing an order touches its neighbours only, never the other orders.
+#[derive(BorshDeserialize, BorshSerialize, Debug)]
+pub struct OrderList {
+    len: u64,
+    first: Option<u64>,
+    last: Option<u64>,
+    /// order_id -> (previous order_id, next order_id)
+    links: LookupMap<u64, (Option<u64>, Option<u64>)>,
+    orders: LookupMap<u64, Order>,
+}
+
+impl OrderList {
+    pub fn for_account(account_id: &AccountId) -> Self {
+        let account_hash = env::sha256_array(account_id.as_bytes());
+        Self::new(
+            StorageKey::AccountOrderLinks { account_hash },
+            StorageKey::AccountOrders { account_hash },
+        )
+    }
+
+    pub fn for_pair(pair_id: &PairId) -> Self {
+        let pair_hash = env::sha256_array(&pair_id.try_to_vec().unwrap());
+        Self::new(
+            StorageKey::PairOrderLinks { pair_hash },
+            StorageKey::PairOrders { pair_hash },
+        )
+    }
+
+    fn new(links: StorageKey, orders: StorageKey) -> Self {
+        Self {
+            len: 0,
+            first: None,
+            last: None,
+            links: LookupMap::new(links),
+            orders: LookupMap::new(orders),
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, order_id: &u64) -> Option<Order> {
+        self.orders.get(order_id)
+    }
+
+    /// Replaces the order if it's in the list, links it after the last one otherwise.
+    pub fn insert(&mut self, order_id: &u64, order: &Order) {
+        if !self.orders.contains_key(order_id) {
+            assert!(
+                self.last.is_none_or(|last| last < *order_id),
+                "The order id is lower than the last one"
+            );
+            self.links.insert(order_id, &(self.last, None));
+            match self.last {
+                Some(last) => self.set_next(last, Some(*order_id)),
+                None => self.first = Some(*order_id),
+            }
+            self.last = Some(*order_id);
+            self.len += 1;
+        }
+        self.orders.insert(order_id, order);
+    }
+
+    pub fn remove(&mut self, order_id: &u64) -> Option<Order> {
+        let order = self.orders.remove(order_id)?;
+        let (previous, next) = self
+            .links
+            .remove(order_id)
+            .expect("The order is not linked");
+        match previous {
+            Some(previous) => self.set_next(previous, next),
+            None => self.first = next,
+        }
+        match next {
+            Some(next) => self.set_previous(next, previous),
+            None => self.last = previous,
+        }
+        self.len -= 1;
+        Some(order)
+    }
+
+    /// The orders by id, each one is read when the iterator reaches it.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, Order)> + '_ {
+        self.iter_from(None)
+    }
+
+    /// The orders by id from `order_id`, nothing if it isn't in the list,
+    /// or from the first order when omitted.
+    pub fn iter_from(&self, order_id: Option<u64>) -> impl Iterator<Item = (u64, Order)> + '_ {
+        std::iter::successors(order_id.or(self.first), |order_id| {
+            self.links.get(order_id).and_then(|(_, next)| next)
+        })
+        .map_while(|order_id| Some((order_id, self.orders.get(&order_id)?)))
+    }
+
+    fn set_next(&mut self, order_id: u64, next: Option<u64>) {
+        let (previous, _) = self.links.get(&order_id).expect("The order is not linked");
+        self.links.insert(&order_id, &(previous, next));
+    }
+
+    fn set_previous(&mut self, order_id: u64, previous: Option<u64>) {
+        let (_, next) = self.links.get(&order_id).expect("The order is not linked");
+        self.links.insert(&order_id, &(previous, next));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{big, usdt, wnear};
+    use near_sdk::test_utils::test_env::alice;
+
+    fn order(amount: &str) -> Order {
+        Order {
+            account_id: alice(),
+            amount: big(amount),
+            sell_token: wnear(),
+            buy_token: usdt(),
+            price: big("2"),
+            side: Side::Sell,
+            time_in_force: TimeInForce::Gtc,
+            status: OrderStatus::Open,
+            filled_amount: BigDecimal::zero(),
+            created_at: U64(0),
+            expires_at: None,
+        }
+    }
+
+    fn ids(list: &OrderList) -> Vec<u64> {
+        list.iter().map(|(order_id, _)| order_id).collect()
+    }
+
+    #[test]
+    fn test_order_list_keeps_the_ids_sorted() {
+        let mut list = OrderList::for_account(&alice());
+        for order_id in 1..=5 {
+            list.insert(&order_id, &order("1"));
+        }
+        list.insert(&3, &order("2"));
+        assert_eq!(list.get(&3).unwrap().amount, big("2"));
+        assert_eq!(ids(&list), [1, 2, 3, 4, 5]);
+
+        // The first, a middle and the last order
+        for order_id in [1, 3, 5] {
+            assert!(list.remove(&order_id).is_some());
+        }
+        assert!(list.remove(&3).is_none());
+        assert_eq!(ids(&list), [2, 4]);
+        assert_eq!(list.len(), 2);
+        let from_4: Vec<u64> = list
+            .iter_from(Some(4))
+            .map(|(order_id, _)| order_id)
+            .collect();
+        assert_eq!(from_4, [4]);
+        assert_eq!(list.iter_from(Some(3)).count(), 0);
+
+        list.insert(&6, &order("1"));
+        assert_eq!(ids(&list), [2, 4, 6]);
+        for order_id in [2, 4, 6] {
+            list.remove(&order_id);
+        }
+        assert!(list.is_empty());
+        assert!(ids(&list).is_empty());
+        list.insert(&7, &order("1"));
+        assert_eq!(ids(&list), [7]);
+    }
+
+    #[test]
+    #[should_panic(expected = "The order id is lower than the last one")]
+    fn test_order_list_rejects_a_lower_id() {
+        let mut list = OrderList::for_pair(&(wnear(), usdt()));
+        list.insert(&2, &order("1"));
+        list.insert(&1, &order("1"));
+    }
+}
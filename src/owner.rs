@@ -0,0 +1,311 @@
+use crate::*;
+use near_sdk::collections::UnorderedSet;
+
+/// Accounts the owner can grant extra permissions to.
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Manages the pairs and their fees
+    Operator,
+    /// Can pause the contract
+    Guardian,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn get_owner(&self) -> AccountId {
+        self.owner_id.clone()
+    }
+
+    pub fn get_pending_owner(&self) -> Option<AccountId> {
+        self.pending_owner_id.clone()
+    }
+
+    /// The first step of the ownership transfer, `None` drops the proposal.
+    /// Only the owner.
+    pub fn propose_owner(&mut self, new_owner_id: Option<AccountId>) {
+        self.assert_owner();
+        self.pending_owner_id = new_owner_id;
+    }
+
+    /// The second step of the ownership transfer, called by the proposed owner.
+    pub fn accept_ownership(&mut self) {
+        let account_id = env::predecessor_account_id();
+        assert_eq!(
+            self.pending_owner_id.as_ref(),
+            Some(&account_id),
+            "Only the proposed owner can accept the ownership"
+        );
+        self.owner_id = account_id;
+        self.pending_owner_id = None;
+    }
+
+    /// Only the owner.
+    pub fn grant_role(&mut self, account_id: AccountId, role: Role) {
+        self.assert_owner();
+        self.role_members_mut(role).insert(&account_id);
+    }
+
+    /// Only the owner.
+    pub fn revoke_role(&mut self, account_id: AccountId, role: Role) {
+        self.assert_owner();
+        self.role_members_mut(role).remove(&account_id);
+    }
+
+    pub fn has_role(&self, account_id: AccountId, role: Role) -> bool {
+        self.role_members(role).contains(&account_id)
+    }
+
+    pub fn get_role_members(&self, role: Role) -> Vec<AccountId> {
+        self.role_members(role).to_vec()
+    }
+
+    /// Stops new orders, cancellations and withdrawals are still allowed.
+    /// Only a guardian or the owner.
+    pub fn pause(&mut self) {
+        let account_id = env::predecessor_account_id();
+        assert!(
+            account_id == self.owner_id || self.guardians.contains(&account_id),
+            "Only the owner or a guardian can call this method"
+        );
+        self.paused = true;
+    }
+
+    /// Only the owner.
+    pub fn unpause(&mut self) {
+        self.assert_owner();
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+}
+
+impl Contract {
+    pub(crate) fn assert_owner(&self) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "Only the owner can call this method"
+        );
+    }
+
+    pub(crate) fn assert_operator(&self) {
+        let account_id = env::predecessor_account_id();
+        assert!(
+            account_id == self.owner_id || self.operators.contains(&account_id),
+            "Only the owner or an operator can call this method"
+        );
+    }
+
+    pub(crate) fn check_not_paused(&self) -> Result<(), OrderError> {
+        if self.paused {
+            return Err(OrderError::Paused);
+        }
+        Ok(())
+    }
+
+    fn role_members(&self, role: Role) -> &UnorderedSet<AccountId> {
+        match role {
+            Role::Operator => &self.operators,
+            Role::Guardian => &self.guardians,
+        }
+    }
+
+    fn role_members_mut(&mut self, role: Role) -> &mut UnorderedSet<AccountId> {
+        match role {
+            Role::Operator => &mut self.operators,
+            Role::Guardian => &mut self.guardians,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{
+        add_order, fund_accounts, new_contract, order_json, set_predecessor, wnear_usdt,
+    };
+    use near_sdk::test_utils::test_env::{alice, bob, carol};
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::testing_env;
+
+    fn owner() -> AccountId {
+        "owner.near".parse().unwrap()
+    }
+
+    #[test]
+    fn test_transfer_ownership() {
+        let mut contract = new_contract(owner());
+        assert_eq!(contract.get_owner(), owner());
+
+        contract.propose_owner(Some(alice()));
+        assert_eq!(contract.get_owner(), owner());
+        assert_eq!(contract.get_pending_owner(), Some(alice()));
+
+        set_predecessor(alice());
+        contract.accept_ownership();
+        assert_eq!(contract.get_owner(), alice());
+        assert_eq!(contract.get_pending_owner(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn test_propose_owner_unauthorized() {
+        let mut contract = new_contract(owner());
+
+        set_predecessor(alice());
+        contract.propose_owner(Some(alice()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the proposed owner can accept the ownership")]
+    fn test_accept_ownership_unauthorized() {
+        let mut contract = new_contract(owner());
+        contract.propose_owner(Some(alice()));
+
+        set_predecessor(bob());
+        contract.accept_ownership();
+    }
+
+    #[test]
+    fn test_roles() {
+        let mut contract = new_contract(owner());
+        contract.grant_role(alice(), Role::Operator);
+        contract.grant_role(bob(), Role::Guardian);
+        assert!(contract.has_role(alice(), Role::Operator));
+        assert!(!contract.has_role(alice(), Role::Guardian));
+        assert_eq!(contract.get_role_members(Role::Guardian), vec![bob()]);
+
+        // An operator manages the pairs
+        set_predecessor(alice());
+        contract.set_default_fees(FeeSchedule {
+            maker_fee_bps: 1,
+            taker_fee_bps: 2,
+        });
+
+        set_predecessor(owner());
+        contract.revoke_role(alice(), Role::Operator);
+        assert!(contract.get_role_members(Role::Operator).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn test_grant_role_unauthorized() {
+        let mut contract = new_contract(owner());
+
+        set_predecessor(alice());
+        contract.grant_role(alice(), Role::Operator);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn test_revoke_role_unauthorized() {
+        let mut contract = new_contract(owner());
+        contract.grant_role(alice(), Role::Guardian);
+
+        set_predecessor(alice());
+        contract.revoke_role(alice(), Role::Guardian);
+    }
+
+    #[test]
+    fn test_pause() {
+        let mut contract = new_contract(owner());
+        fund_accounts(&mut contract);
+        let order_id = add_order(&mut contract, alice(), "sell", "2", "10");
+
+        set_predecessor(owner());
+        contract.grant_role(bob(), Role::Guardian);
+        set_predecessor(bob());
+        contract.pause();
+        assert!(contract.is_paused());
+
+        set_predecessor(alice());
+        assert_eq!(
+            contract.add_order_from_string(order_json(&alice(), "sell", "2", "10")),
+            Err(OrderError::Paused)
+        );
+
+        // Cancellations and withdrawals still work
+        contract.cancel_order(order_id);
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(alice())
+            .attached_deposit(1)
+            .build());
+        contract.withdraw("wnear.near".parse().unwrap(), 10.into());
+
+        set_predecessor(owner());
+        contract.unpause();
+        set_predecessor(alice());
+        assert!(contract
+            .add_order_from_string(order_json(&alice(), "sell", "2", "10"))
+            .is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner or a guardian can call this method")]
+    fn test_pause_unauthorized() {
+        let mut contract = new_contract(owner());
+        contract.grant_role(alice(), Role::Operator);
+
+        set_predecessor(alice());
+        contract.pause();
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn test_unpause_unauthorized() {
+        let mut contract = new_contract(owner());
+        contract.grant_role(alice(), Role::Guardian);
+        set_predecessor(alice());
+        contract.pause();
+
+        contract.unpause();
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner or an operator can call this method")]
+    fn test_set_pair_enabled_unauthorized() {
+        let mut contract = new_contract(owner());
+        fund_accounts(&mut contract);
+
+        set_predecessor(carol());
+        contract.set_pair_enabled(wnear_usdt(), false);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner or an operator can call this method")]
+    fn test_set_default_fees_unauthorized() {
+        let mut contract = new_contract(owner());
+
+        set_predecessor(carol());
+        contract.set_default_fees(FeeSchedule::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner or an operator can call this method")]
+    fn test_set_pair_fees_unauthorized() {
+        let mut contract = new_contract(owner());
+        fund_accounts(&mut contract);
+
+        set_predecessor(carol());
+        contract.set_pair_fees(wnear_usdt(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn test_withdraw_fees_operator() {
+        let mut contract = new_contract(owner());
+        contract.grant_role(alice(), Role::Operator);
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(alice())
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_fees("usdt.near".parse().unwrap(), 1.into());
+    }
+}
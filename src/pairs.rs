@@ -0,0 +1,389 @@
+use crate::*;
+use near_contract_standards::fungible_token::metadata::{ext_ft_metadata, FungibleTokenMetadata};
+use near_sdk::{Gas, Promise, PromiseResult};
+
+const GAS_FOR_FT_METADATA: Gas = Gas(10_000_000_000_000);
+const GAS_FOR_RESOLVE_ADD_PAIR: Gas = Gas(10_000_000_000_000);
+
+/// A market the owner has opened, orders are only accepted for registered pairs.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Pair {
+    pub base: AccountId,
+    pub quote: AccountId,
+    /// "decimals" of the "ft_metadata" of the base token
+    pub base_decimals: u8,
+    /// "decimals" of the "ft_metadata" of the quote token
+    pub quote_decimals: u8,
+    /// Step of the price, in the quote token per base token
+    pub tick_size: BigDecimal,
+    /// Step of the amount, in the base token
+    pub lot_size: BigDecimal,
+    /// Smallest amount times price of an order, in the quote token
+    pub min_notional: BigDecimal,
+    /// Overrides the default fees of the contract
+    pub fees: Option<FeeSchedule>,
+    /// A disabled pair doesn't accept new orders
+    pub enabled: bool,
+}
+
+impl Pair {
+    pub fn pair_id(&self) -> PairId {
+        (self.base.clone(), self.quote.clone())
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Registers the (base, quote) pair after fetching the metadata of both tokens.
+    /// Only the owner or an operator can add pairs.
+    pub fn add_pair(
+        &mut self,
+        base: AccountId,
+        quote: AccountId,
+        tick_size: BigDecimal,
+        lot_size: BigDecimal,
+        min_notional: BigDecimal,
+    ) -> Promise {
+        self.assert_operator();
+        assert_ne!(base, quote, "The base and quote tokens are the same");
+        assert!(
+            tick_size > BigDecimal::zero() && lot_size > BigDecimal::zero(),
+            "The tick size and the lot size must be positive"
+        );
+        self.assert_pair_not_registered(&base, &quote);
+
+        ext_ft_metadata::ext(base.clone())
+            .with_static_gas(GAS_FOR_FT_METADATA)
+            .ft_metadata()
+            .and(
+                ext_ft_metadata::ext(quote.clone())
+                    .with_static_gas(GAS_FOR_FT_METADATA)
+                    .ft_metadata(),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_ADD_PAIR)
+                    .resolve_add_pair(base, quote, tick_size, lot_size, min_notional),
+            )
+    }
+
+    /// The pair is checked again, another "add_pair" of the same tokens
+    /// may have been resolved while the metadata was fetched.
+    #[private]
+    pub fn resolve_add_pair(
+        &mut self,
+        base: AccountId,
+        quote: AccountId,
+        tick_size: BigDecimal,
+        lot_size: BigDecimal,
+        min_notional: BigDecimal,
+    ) -> Pair {
+        self.assert_pair_not_registered(&base, &quote);
+        let base_metadata = promise_metadata(0, &base);
+        let quote_metadata = promise_metadata(1, &quote);
+
+        let pair = Pair {
+            base,
+            quote,
+            base_decimals: base_metadata.decimals,
+            quote_decimals: quote_metadata.decimals,
+            tick_size,
+            lot_size,
+            min_notional,
+            fees: None,
+            enabled: true,
+        };
+        self.internal_add_pair(&pair);
+        pair
+    }
+
+    /// Only the owner or an operator can enable or disable a pair.
+    /// Open orders of a disabled pair stay in the book and can be cancelled.
+    pub fn set_pair_enabled(&mut self, pair_id: PairId, enabled: bool) {
+        self.assert_operator();
+        let mut pair = self.pairs.get(&pair_id).expect("Pair not found");
+        pair.enabled = enabled;
+        self.pairs.insert(&pair_id, &pair);
+    }
+
+    pub fn get_pair(&self, pair_id: PairId) -> Option<Pair> {
+        self.pairs.get(&pair_id)
+    }
+
+    /// Number of registered pairs, read from the length of the map.
+    pub fn get_num_pairs(&self) -> u64 {
+        self.pairs.len()
+    }
+
+    /// Registered pairs in the order they were added.
+    pub fn list_pairs(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<Pair> {
+        let pairs = self.pairs.values_as_vector();
+        let from_index = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(DEFAULT_LIMIT);
+        (from_index..std::cmp::min(from_index.saturating_add(limit), pairs.len()))
+            .filter_map(|index| pairs.get(index))
+            .collect()
+    }
+}
+
+impl Contract {
+    /// Both orders of the tokens are checked, a market has a single pair.
+    fn assert_pair_not_registered(&self, base: &AccountId, quote: &AccountId) {
+        assert!(
+            self.market_pair_id(base, quote).is_none(),
+            "The pair is already registered"
+        );
+    }
+
+    pub(crate) fn internal_add_pair(&mut self, pair: &Pair) {
+        self.pairs.insert(&pair.pair_id(), pair);
+    }
+
+    /// (base, quote) of the registered pair of the two tokens, in either order.
+    pub(crate) fn market_pair_id(
+        &self,
+        token_a: &AccountId,
+        token_b: &AccountId,
+    ) -> Option<PairId> {
+        [
+            (token_a.clone(), token_b.clone()),
+            (token_b.clone(), token_a.clone()),
+        ]
+        .into_iter()
+        .find(|pair_id| self.pairs.get(pair_id).is_some())
+    }
+
+    /// Puts the order on the book of its registered pair: selling the base token
+    /// makes a sell order, selling the quote token makes a buy order.
+    /// The pair must be enabled. The amount is rounded down to the lot size,
+    /// the price must be on the tick size and the order must reach the minimum notional.
+    pub(crate) fn internal_normalize_order(&self, order: &mut Order) -> Result<(), OrderError> {
+        let pair = self
+            .market_pair_id(&order.sell_token, &order.buy_token)
+            .and_then(|pair_id| self.pairs.get(&pair_id))
+            .ok_or_else(|| {
+                OrderError::UnknownPair((order.sell_token.clone(), order.buy_token.clone()))
+            })?;
+        if !pair.enabled {
+            return Err(OrderError::PairDisabled(pair.pair_id()));
+        }
+
+        order.side = if order.sell_token == pair.base {
+            Side::Sell
+        } else {
+            Side::Buy
+        };
+
+        if !order.price.is_multiple_of(&pair.tick_size) {
+            return Err(OrderError::InvalidTick(pair.tick_size));
+        }
+        order.amount = order.amount.floor_to_step(&pair.lot_size);
+        if order.amount == BigDecimal::zero() {
+            return Err(OrderError::BelowLotSize(pair.lot_size));
+        }
+        if order.amount * order.price < pair.min_notional {
+            return Err(OrderError::BelowMinNotional(pair.min_notional));
+        }
+        Ok(())
+    }
+}
+
+fn promise_metadata(result_index: u64, token_id: &AccountId) -> FungibleTokenMetadata {
+    match env::promise_result(result_index) {
+        PromiseResult::Successful(value) => serde_json::from_slice(&value)
+            .unwrap_or_else(|_| panic!("Invalid metadata of {}", token_id)),
+        _ => panic!("Can't get the metadata of {}", token_id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{
+        big, deposit, fund_accounts, order_json, set_predecessor, usdt, wnear,
+    };
+    use near_contract_standards::fungible_token::metadata::FT_METADATA_SPEC;
+    use near_sdk::test_utils::test_env::{alice, bob};
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, RuntimeFeesConfig, VMConfig};
+
+    fn metadata(decimals: u8) -> Vec<u8> {
+        serde_json::to_vec(&FungibleTokenMetadata {
+            spec: FT_METADATA_SPEC.to_string(),
+            name: "Token".to_string(),
+            symbol: "TKN".to_string(),
+            icon: None,
+            reference: None,
+            reference_hash: None,
+            decimals,
+        })
+        .unwrap()
+    }
+
+    /// The context of "resolve_add_pair" with the metadata of both tokens.
+    fn set_metadata_results(base_decimals: u8, quote_decimals: u8) {
+        testing_env!(
+            VMContextBuilder::new()
+                .predecessor_account_id(env::current_account_id())
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![
+                PromiseResult::Successful(metadata(base_decimals)),
+                PromiseResult::Successful(metadata(quote_decimals)),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_add_pair() {
+        set_predecessor(alice());
+        let mut contract = Contract::new();
+        assert_eq!(contract.get_owner(), alice());
+
+        contract.add_pair(wnear(), usdt(), big("0.01"), big("0.1"), big("1"));
+        set_metadata_results(24, 6);
+        let pair = contract.resolve_add_pair(wnear(), usdt(), big("0.01"), big("0.1"), big("1"));
+        assert_eq!(pair.base_decimals, 24);
+        assert_eq!(pair.quote_decimals, 6);
+
+        assert_eq!(contract.list_pairs(None, None), vec![pair.clone()]);
+        assert_eq!(contract.get_pair((wnear(), usdt())), Some(pair));
+        assert!(contract.list_pairs(Some(1), None).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "The pair is already registered")]
+    fn test_add_pair_twice_in_flight() {
+        set_predecessor(alice());
+        let mut contract = Contract::new();
+
+        // Both calls pass the first check before either one is resolved
+        contract.add_pair(wnear(), usdt(), big("0.01"), big("0.1"), big("1"));
+        contract.add_pair(usdt(), wnear(), big("0.01"), big("0.1"), big("1"));
+        set_metadata_results(24, 6);
+        contract.resolve_add_pair(wnear(), usdt(), big("0.01"), big("0.1"), big("1"));
+        set_metadata_results(6, 24);
+        contract.resolve_add_pair(usdt(), wnear(), big("0.01"), big("0.1"), big("1"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner or an operator can call this method")]
+    fn test_add_pair_not_owner() {
+        set_predecessor(alice());
+        let mut contract = Contract::new();
+
+        set_predecessor(bob());
+        contract.add_pair(wnear(), usdt(), big("0.01"), big("0.1"), big("1"));
+    }
+
+    #[test]
+    fn test_unknown_and_disabled_pair() {
+        set_predecessor(alice());
+        let mut contract = Contract::new();
+        deposit(&mut contract, bob(), wnear(), 10);
+
+        set_predecessor(bob());
+        assert_eq!(
+            contract.add_order_from_string(order_json(&bob(), "sell", "2", "10")),
+            Err(OrderError::UnknownPair((wnear(), usdt())))
+        );
+
+        contract.internal_add_pair(&Pair {
+            base: wnear(),
+            quote: usdt(),
+            base_decimals: 24,
+            quote_decimals: 6,
+            tick_size: big("0.01"),
+            lot_size: big("0.1"),
+            min_notional: big("1"),
+            fees: None,
+            enabled: true,
+        });
+        set_predecessor(alice());
+        contract.set_pair_enabled((wnear(), usdt()), false);
+
+        set_predecessor(bob());
+        assert_eq!(
+            contract.add_order_from_string(order_json(&bob(), "sell", "2", "10")),
+            Err(OrderError::PairDisabled((wnear(), usdt())))
+        );
+
+        set_predecessor(alice());
+        contract.set_pair_enabled((wnear(), usdt()), true);
+        set_predecessor(bob());
+        assert!(contract
+            .add_order_from_string(order_json(&bob(), "sell", "2", "10"))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_both_directions_share_one_book() {
+        let mut contract = Contract::new();
+        fund_accounts(&mut contract);
+
+        // Alice sells wNEAR, Bob sells USDT: an ask and a bid of the same book
+        let ask = "{\"account_id\":\"alice.near\",\"amount\":\"10\",\"sell_token\":\"wnear.near\",\"buy_token\":\"usdt.near\",\"price\":\"2\"}".to_string();
+        set_predecessor(alice());
+        let ask_id = contract.add_order_from_string_1(ask);
+        let bid = "{\"account_id\":\"bob.near\",\"amount\":\"4\",\"sell_token\":\"usdt.near\",\"buy_token\":\"wnear.near\",\"price\":\"2\"}".to_string();
+        set_predecessor(bob());
+        contract.add_order_from_string_1(bid);
+
+        let ask = contract.get_order(ask_id).unwrap();
+        assert_eq!(ask.side, Side::Sell);
+        assert_eq!(ask.amount, big("6"));
+        assert_eq!(
+            contract
+                .get_trades_by_pair((wnear(), usdt()), None, None)
+                .len(),
+            1
+        );
+
+        // The per-direction views only show the orders selling the first token
+        assert_eq!(
+            contract
+                .view_ref_orders((wnear(), usdt()), None, None, None)
+                .len(),
+            1
+        );
+        assert!(contract
+            .view_ref_orders((usdt(), wnear()), None, None, None)
+            .is_empty());
+        assert!(contract
+            .view_ref_orders((usdt(), "dai.near".parse().unwrap()), None, None, None)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_tick_lot_and_min_notional() {
+        let mut contract = Contract::new();
+        fund_accounts(&mut contract);
+        set_predecessor(alice());
+
+        // The tick size and the lot size of the test pair are 0.01
+        assert_eq!(
+            contract.add_order_from_string(order_json(&alice(), "sell", "2.005", "10")),
+            Err(OrderError::InvalidTick(big("0.01")))
+        );
+        assert_eq!(
+            contract.add_order_from_string(order_json(&alice(), "sell", "2", "0.009")),
+            Err(OrderError::BelowLotSize(big("0.01")))
+        );
+        assert_eq!(
+            contract.add_order_from_string(order_json(&alice(), "sell", "2", "0.49")),
+            Err(OrderError::BelowMinNotional(big("1")))
+        );
+
+        let order_id = contract
+            .add_order_from_string(order_json(&alice(), "sell", "2", "10.0099"))
+            .unwrap();
+        assert_eq!(
+            contract.get_order(order_id.into()).unwrap().amount,
+            big("10")
+        );
+        assert_eq!(contract.get_balance(alice(), wnear()).locked, big("10"));
+    }
+}
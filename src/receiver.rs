@@ -0,0 +1,192 @@
+use crate::*;
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_sdk::json_types::U128;
+use near_sdk::PromiseOrValue;
+
+#[near_bindgen]
+impl FungibleTokenReceiver for Contract {
+    /// With an empty `msg` the tokens are deposited to the sender's balance.
+    /// Otherwise `msg` is the JSON of the order, the same as in `add_order_from_string_*`.
+    /// The transfer must come from the "sell_token" of the order and cover its sell amount.
+    /// The whole tokens left are returned to the sender, a fraction of a token
+    /// that can't be returned is added to the sender's available balance.
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        let unused = self
+            .internal_ft_on_transfer(&env::predecessor_account_id(), &sender_id, amount.0, &msg)
+            .unwrap_or_else(|err| env::panic_str(&err.to_string()));
+        PromiseOrValue::Value(U128(unused))
+    }
+}
+
+impl Contract {
+    /// Returns the amount of tokens to give back to the sender.
+    pub(crate) fn internal_ft_on_transfer(
+        &mut self,
+        token_id: &AccountId,
+        sender_id: &AccountId,
+        amount: u128,
+        msg: &str,
+    ) -> Result<u128, OrderError> {
+        if msg.is_empty() {
+            let initial_storage_usage = env::storage_usage();
+            self.internal_deposit(sender_id, token_id, BigDecimal::from(amount));
+            self.internal_update_storage(sender_id, initial_storage_usage);
+            return Ok(0);
+        }
+
+        let order = self.internal_parse_order(sender_id, msg)?;
+        if token_id != &order.sell_token {
+            return Err(OrderError::WrongToken(token_id.clone()));
+        }
+
+        let deposit = BigDecimal::from(amount);
+        let required = order.sell_amount();
+        if deposit < required {
+            return Err(OrderError::DepositTooSmall(required));
+        }
+
+        let refund = (deposit - required).floor_u128();
+        let initial_storage_usage = env::storage_usage();
+        self.internal_deposit(sender_id, token_id, deposit - BigDecimal::from(refund));
+        self.internal_update_storage(sender_id, initial_storage_usage);
+        self.internal_add_order(sender_id, order)?;
+
+        Ok(refund)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{order_json, register, register_pair, set_predecessor};
+    use near_sdk::test_utils::test_env::{alice, bob};
+    use std::str::FromStr;
+
+    fn unused(result: PromiseOrValue<U128>) -> u128 {
+        match result {
+            PromiseOrValue::Value(value) => value.0,
+            PromiseOrValue::Promise(_) => panic!("Expected a value"),
+        }
+    }
+
+    #[test]
+    fn test_ft_on_transfer() {
+        let mut contract = Contract::new();
+        register_pair(&mut contract, "wnear.near", "usdt.near");
+        register(&mut contract, alice());
+
+        set_predecessor("wnear.near".parse().unwrap());
+        let result =
+            contract.ft_on_transfer(alice(), U128(15), order_json(&alice(), "sell", "2", "10"));
+        assert_eq!(unused(result), 5);
+
+        // A buy order spends the quote token: 4 * 1.5 = 6
+        set_predecessor("usdt.near".parse().unwrap());
+        let result =
+            contract.ft_on_transfer(alice(), U128(6), order_json(&alice(), "buy", "1.5", "4"));
+        assert_eq!(unused(result), 0);
+
+        let orders = contract.view_orders(alice(), None, None, None);
+        assert_eq!(orders.len(), 2);
+        assert_eq!(orders[0].1.account_id, alice());
+
+        let balances = contract.get_balances(alice());
+        assert_eq!(balances.len(), 2);
+        assert!(balances
+            .iter()
+            .all(|(_, balance)| balance.available == BigDecimal::zero()));
+    }
+
+    #[test]
+    fn test_ft_on_transfer_keeps_the_fraction() {
+        let mut contract = Contract::new();
+        register_pair(&mut contract, "wnear.near", "usdt.near");
+        register(&mut contract, alice());
+
+        // 4 * 1.55 = 6.2 is locked, the 0.8 left can't be returned
+        set_predecessor("usdt.near".parse().unwrap());
+        let result =
+            contract.ft_on_transfer(alice(), U128(7), order_json(&alice(), "buy", "1.55", "4"));
+        assert_eq!(unused(result), 0);
+
+        let balance = contract.get_balance(alice(), "usdt.near".parse().unwrap());
+        assert_eq!(balance.locked, BigDecimal::from_str("6.2").unwrap());
+        assert_eq!(balance.available, BigDecimal::from_str("0.8").unwrap());
+    }
+
+    #[test]
+    fn test_ft_on_transfer_deposit() {
+        let mut contract = Contract::new();
+        register_pair(&mut contract, "wnear.near", "usdt.near");
+        register(&mut contract, alice());
+
+        set_predecessor("usdt.near".parse().unwrap());
+        let result = contract.ft_on_transfer(alice(), U128(15), String::new());
+        assert_eq!(unused(result), 0);
+        assert_eq!(
+            contract
+                .get_balance(alice(), "usdt.near".parse().unwrap())
+                .available,
+            BigDecimal::from(15)
+        );
+    }
+
+    #[test]
+    fn test_ft_on_transfer_wrong_token() {
+        let mut contract = Contract::new();
+        register_pair(&mut contract, "wnear.near", "usdt.near");
+        let usdt: AccountId = "usdt.near".parse().unwrap();
+
+        set_predecessor(usdt.clone());
+        let err = contract
+            .internal_ft_on_transfer(
+                &usdt,
+                &alice(),
+                100,
+                &order_json(&alice(), "sell", "2", "10"),
+            )
+            .unwrap_err();
+        assert_eq!(err, OrderError::WrongToken(usdt));
+        assert!(err.to_string().starts_with("E017_WRONG_TOKEN"));
+    }
+
+    #[test]
+    fn test_ft_on_transfer_for_another_account() {
+        let mut contract = Contract::new();
+        register_pair(&mut contract, "wnear.near", "usdt.near");
+        register(&mut contract, alice());
+        register(&mut contract, bob());
+        let wnear: AccountId = "wnear.near".parse().unwrap();
+
+        // Alice funds an order that claims to be Bob's
+        set_predecessor(wnear.clone());
+        let err = contract
+            .internal_ft_on_transfer(&wnear, &alice(), 10, &order_json(&bob(), "sell", "2", "10"))
+            .unwrap_err();
+        assert_eq!(err, OrderError::AccountMismatch(bob()));
+        assert!(err.to_string().starts_with("E006_ACCOUNT_MISMATCH"));
+
+        assert!(contract.view_orders(bob(), None, None, None).is_empty());
+        assert!(contract.view_orders(alice(), None, None, None).is_empty());
+        assert!(contract.get_balances(alice()).is_empty());
+    }
+
+    #[test]
+    fn test_ft_on_transfer_not_enough() {
+        let mut contract = Contract::new();
+        register_pair(&mut contract, "wnear.near", "usdt.near");
+        let usdt: AccountId = "usdt.near".parse().unwrap();
+
+        set_predecessor(usdt.clone());
+        let err = contract
+            .internal_ft_on_transfer(&usdt, &alice(), 5, &order_json(&alice(), "buy", "1.5", "4"))
+            .unwrap_err();
+        assert_eq!(err, OrderError::DepositTooSmall(BigDecimal::from(6)));
+        assert!(err.to_string().starts_with("E018_DEPOSIT_TOO_SMALL"));
+    }
+}
@@ -0,0 +1,450 @@
+use crate::events::{Event, OrderEvent};
+use crate::*;
+use near_contract_standards::storage_management::{
+    StorageBalance, StorageBalanceBounds, StorageManagement,
+};
+use near_sdk::json_types::U128;
+use near_sdk::{assert_one_yocto, Promise, StorageUsage};
+
+/// Charged at registration for the account's own records
+pub const ACCOUNT_STORAGE_BYTES: StorageUsage = 200;
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, Default)]
+pub struct AccountStorage {
+    /// Attached yoctoNEAR
+    pub deposit: u128,
+    /// Bytes the account pays for
+    pub used_bytes: StorageUsage,
+}
+
+impl AccountStorage {
+    fn used(&self) -> u128 {
+        self.used_bytes as u128 * env::storage_byte_cost()
+    }
+
+    fn available(&self) -> u128 {
+        self.deposit.saturating_sub(self.used())
+    }
+
+    fn to_storage_balance(&self) -> StorageBalance {
+        StorageBalance {
+            total: U128(self.deposit),
+            available: U128(self.available()),
+        }
+    }
+}
+
+#[near_bindgen]
+impl StorageManagement for Contract {
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let amount = env::attached_deposit();
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let registration_only = registration_only.unwrap_or(false);
+
+        let storage = match self.storage_accounts.get(&account_id) {
+            Some(mut storage) => {
+                if registration_only {
+                    Promise::new(env::predecessor_account_id()).transfer(amount);
+                } else {
+                    storage.deposit += amount;
+                }
+                storage
+            }
+            None => {
+                let min_balance = self.storage_balance_bounds().min.0;
+                assert!(
+                    amount >= min_balance,
+                    "The attached deposit is less than the minimum storage balance {}",
+                    min_balance
+                );
+                let deposit = if registration_only {
+                    if amount > min_balance {
+                        Promise::new(env::predecessor_account_id()).transfer(amount - min_balance);
+                    }
+                    min_balance
+                } else {
+                    amount
+                };
+                AccountStorage {
+                    deposit,
+                    used_bytes: ACCOUNT_STORAGE_BYTES,
+                }
+            }
+        };
+
+        self.storage_accounts.insert(&account_id, &storage);
+        storage.to_storage_balance()
+    }
+
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let mut storage = self
+            .storage_accounts
+            .get(&account_id)
+            .expect("The account is not registered");
+
+        let available = storage.available();
+        let amount = amount.map(|amount| amount.0).unwrap_or(available);
+        assert!(
+            amount <= available,
+            "The amount is greater than the available storage balance"
+        );
+
+        storage.deposit -= amount;
+        self.storage_accounts.insert(&account_id, &storage);
+        if amount > 0 {
+            Promise::new(account_id).transfer(amount);
+        }
+        storage.to_storage_balance()
+    }
+
+    /// Without `force` the account must have no open orders and no tokens in the ledger,
+    /// an available balance below one unit can't be withdrawn and goes to the fee vault.
+    /// With `force` the open orders are removed and the tokens of the account are burnt.
+    /// The order history of the account is removed in both cases.
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let storage = match self.storage_accounts.get(&account_id) {
+            Some(storage) => storage,
+            None => return false,
+        };
+
+        let order_ids: Vec<u64> = self
+            .orders
+            .get(&account_id)
+            .map(|orders| orders.iter().map(|(order_id, _)| order_id).collect())
+            .unwrap_or_default();
+        let balances = self.get_balances(account_id.clone());
+        let has_tokens = balances.iter().any(|(_, balance)| {
+            balance.locked != BigDecimal::zero() || balance.available >= BigDecimal::one()
+        });
+
+        if !force.unwrap_or(false) {
+            assert!(
+                order_ids.is_empty() && !has_tokens,
+                "Can't unregister the account with open orders or tokens"
+            );
+            for (token_id, balance) in balances {
+                if balance.available != BigDecimal::zero() {
+                    self.internal_collect_fee(&token_id, balance.available);
+                }
+            }
+        }
+
+        for order_id in order_ids {
+            let (_, pair_id) = self.order_index.get(&order_id).expect("Order not found");
+            let order = self.internal_remove_order(&account_id, &pair_id, order_id);
+            Event::OrderCancelled(vec![OrderEvent::new(order_id, &order)]).emit();
+        }
+        self.internal_clear_history(&account_id);
+        if let Some(mut balances) = self.balances.remove(&account_id) {
+            balances.clear();
+        }
+
+        self.storage_accounts.remove(&account_id);
+        Promise::new(account_id).transfer(storage.deposit);
+        true
+    }
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: U128(ACCOUNT_STORAGE_BYTES as u128 * env::storage_byte_cost()),
+            max: None,
+        }
+    }
+
+    fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.storage_accounts
+            .get(&account_id)
+            .map(|storage| storage.to_storage_balance())
+    }
+}
+
+impl Contract {
+    /// Charges the account for the storage grown since `initial_storage_usage`,
+    /// or gives back the released storage.
+    pub(crate) fn internal_update_storage(
+        &mut self,
+        account_id: &AccountId,
+        initial_storage_usage: StorageUsage,
+    ) {
+        let mut storage = self
+            .storage_accounts
+            .get(account_id)
+            .unwrap_or_else(|| panic!("The account {} is not registered", account_id));
+
+        let storage_usage = env::storage_usage();
+        if storage_usage >= initial_storage_usage {
+            storage.used_bytes += storage_usage - initial_storage_usage;
+        } else {
+            storage.used_bytes = storage
+                .used_bytes
+                .saturating_sub(initial_storage_usage - storage_usage);
+        }
+
+        assert!(
+            storage.used() <= storage.deposit,
+            "Not enough storage deposit: {} yoctoNEAR required, {} deposited",
+            storage.used(),
+            storage.deposit
+        );
+        self.storage_accounts.insert(account_id, &storage);
+    }
+
+    /// Adds the NEAR attached to a call of the account to its storage deposit.
+    pub(crate) fn internal_add_storage_deposit(&mut self, account_id: &AccountId, amount: u128) {
+        if amount == 0 {
+            return;
+        }
+        let mut storage = self
+            .storage_accounts
+            .get(account_id)
+            .unwrap_or_else(|| panic!("The account {} is not registered", account_id));
+        storage.deposit += amount;
+        self.storage_accounts.insert(account_id, &storage);
+    }
+
+    /// Takes up to `amount` yoctoNEAR of the storage deposit the account doesn't use.
+    /// Returns what was taken.
+    pub(crate) fn internal_take_storage_deposit(
+        &mut self,
+        account_id: &AccountId,
+        amount: u128,
+    ) -> u128 {
+        let mut storage = match self.storage_accounts.get(account_id) {
+            Some(storage) => storage,
+            None => return 0,
+        };
+        let amount = amount.min(storage.available());
+        storage.deposit -= amount;
+        self.storage_accounts.insert(account_id, &storage);
+        amount
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{add_order, big, order_json, register, register_pair, set_predecessor};
+    use near_sdk::test_utils::test_env::{alice, bob};
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::testing_env;
+    use near_sdk::ONE_NEAR;
+
+    fn set_deposit(account_id: AccountId, attached_deposit: u128) {
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(account_id)
+            .attached_deposit(attached_deposit)
+            .build());
+    }
+
+    #[test]
+    fn test_storage_deposit() {
+        let mut contract = Contract::new();
+        let min_balance = contract.storage_balance_bounds().min.0;
+
+        assert!(contract.storage_balance_of(alice()).is_none());
+
+        set_deposit(alice(), ONE_NEAR);
+        let balance = contract.storage_deposit(None, Some(true));
+        assert_eq!(balance.total.0, min_balance);
+        assert_eq!(balance.available.0, 0);
+
+        set_deposit(bob(), ONE_NEAR);
+        contract.storage_deposit(Some(alice()), None);
+        let balance = contract.storage_balance_of(alice()).unwrap();
+        assert_eq!(balance.total.0, min_balance + ONE_NEAR);
+        assert_eq!(balance.available.0, ONE_NEAR);
+    }
+
+    #[test]
+    #[should_panic(expected = "The attached deposit is less than the minimum storage balance")]
+    fn test_storage_deposit_too_small() {
+        let mut contract = Contract::new();
+
+        set_deposit(alice(), 1);
+        contract.storage_deposit(None, None);
+    }
+
+    #[test]
+    fn test_orders_are_charged() {
+        let mut contract = Contract::new();
+        contract.set_history_retention(0);
+
+        register(&mut contract, alice());
+        let available = contract.storage_balance_of(alice()).unwrap().available.0;
+
+        let order_id = add_order(&mut contract, alice(), "sell", "2", "10");
+        let charged = available - contract.storage_balance_of(alice()).unwrap().available.0;
+        assert!(charged > 0);
+
+        set_predecessor(alice());
+        contract.cancel_order(order_id);
+        assert_eq!(
+            contract.storage_balance_of(alice()).unwrap().available.0,
+            available
+        );
+    }
+
+    #[test]
+    fn test_filled_maker_is_released() {
+        let mut contract = Contract::new();
+        contract.set_history_retention(0);
+
+        register(&mut contract, alice());
+        let available = contract.storage_balance_of(alice()).unwrap().available.0;
+
+        // Bob's order fills Alice's one and pays for the removal
+        add_order(&mut contract, alice(), "sell", "2", "10");
+        add_order(&mut contract, bob(), "buy", "2", "10");
+        assert_eq!(
+            contract.storage_balance_of(alice()).unwrap().available.0,
+            available
+        );
+    }
+
+    #[test]
+    fn test_history_is_charged() {
+        let mut contract = Contract::new();
+
+        register(&mut contract, alice());
+        let available = contract.storage_balance_of(alice()).unwrap().available.0;
+
+        let order_id = add_order(&mut contract, alice(), "sell", "2", "10");
+        let with_order = contract.storage_balance_of(alice()).unwrap().available.0;
+        set_predecessor(alice());
+        contract.cancel_order(order_id);
+        let with_history = contract.storage_balance_of(alice()).unwrap().available.0;
+        assert!(with_order < with_history && with_history < available);
+
+        set_deposit(alice(), 1);
+        assert!(contract.storage_unregister(Some(true)));
+        assert!(contract.view_order_history(alice(), None, None).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough storage deposit")]
+    fn test_orders_not_enough_storage() {
+        let mut contract = Contract::new();
+
+        set_deposit(alice(), contract.storage_balance_bounds().min.0);
+        contract.storage_deposit(None, None);
+        add_order(&mut contract, alice(), "sell", "2", "10");
+    }
+
+    #[test]
+    fn test_order_deposit_is_added() {
+        let mut contract = Contract::new();
+        register_pair(&mut contract, "wnear.near", "usdt.near");
+        let min_balance = contract.storage_balance_bounds().min.0;
+        set_deposit(alice(), min_balance);
+        contract.storage_deposit(None, None);
+        contract.internal_deposit(
+            &alice(),
+            &"wnear.near".parse().unwrap(),
+            BigDecimal::from(10),
+        );
+
+        // The NEAR attached to the order pays for its storage
+        set_deposit(alice(), ONE_NEAR);
+        contract.add_order_from_string_3(order_json(&alice(), "sell", "2", "10"));
+        let balance = contract.storage_balance_of(alice()).unwrap();
+        assert_eq!(balance.total.0, min_balance + ONE_NEAR);
+        assert!(balance.available.0 < ONE_NEAR);
+    }
+
+    #[test]
+    #[should_panic(expected = "The account alice.near is not registered")]
+    fn test_orders_not_registered() {
+        let mut contract = Contract::new();
+        register_pair(&mut contract, "wnear.near", "usdt.near");
+
+        contract.internal_deposit(
+            &alice(),
+            &"wnear.near".parse().unwrap(),
+            BigDecimal::from(10),
+        );
+        set_predecessor(alice());
+        contract.add_order_from_string_3(order_json(&alice(), "sell", "2", "10"));
+    }
+
+    #[test]
+    fn test_storage_withdraw_and_unregister() {
+        let mut contract = Contract::new();
+
+        register(&mut contract, alice());
+        set_deposit(alice(), 1);
+        let balance = contract.storage_withdraw(None);
+        assert_eq!(balance.available.0, 0);
+
+        set_deposit(alice(), 1);
+        assert!(contract.storage_unregister(None));
+        assert!(contract.storage_balance_of(alice()).is_none());
+
+        set_deposit(alice(), 1);
+        assert!(!contract.storage_unregister(None));
+    }
+
+    #[test]
+    fn test_storage_unregister_sweeps_dust() {
+        let mut contract = Contract::new();
+
+        register(&mut contract, alice());
+        contract.internal_deposit(&alice(), &"usdt.near".parse().unwrap(), big("0.5"));
+        set_deposit(alice(), 1);
+        assert!(contract.storage_unregister(None));
+        assert!(contract.get_balances(alice()).is_empty());
+        assert_eq!(
+            contract.get_fee_vault(),
+            vec![("usdt.near".parse().unwrap(), big("0.5"))]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't unregister the account with open orders or tokens")]
+    fn test_storage_unregister_one_unit() {
+        let mut contract = Contract::new();
+
+        register(&mut contract, alice());
+        contract.internal_deposit(&alice(), &"usdt.near".parse().unwrap(), big("1"));
+        set_deposit(alice(), 1);
+        contract.storage_unregister(None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't unregister the account with open orders or tokens")]
+    fn test_storage_unregister_open_orders() {
+        let mut contract = Contract::new();
+
+        add_order(&mut contract, alice(), "sell", "2", "10");
+        set_deposit(alice(), 1);
+        contract.storage_unregister(None);
+    }
+
+    #[test]
+    fn test_storage_unregister_force() {
+        let mut contract = Contract::new();
+
+        let order_id = add_order(&mut contract, alice(), "sell", "2", "10");
+        set_deposit(alice(), 1);
+        assert!(contract.storage_unregister(Some(true)));
+        assert!(contract.get_order(order_id).is_none());
+        assert!(contract.get_balances(alice()).is_empty());
+        assert!(contract
+            .view_order_book(
+                ("wnear.near".parse().unwrap(), "usdt.near".parse().unwrap()),
+                10
+            )
+            .asks
+            .is_empty());
+    }
+}
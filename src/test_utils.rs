@@ -0,0 +1,125 @@
+use crate::*;
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_contract_standards::storage_management::StorageManagement;
+use near_sdk::json_types::U128;
+use near_sdk::test_utils::VMContextBuilder;
+use near_sdk::{testing_env, ONE_NEAR};
+use std::str::FromStr;
+
+pub fn big(value: &str) -> BigDecimal {
+    BigDecimal::from_str(value).unwrap()
+}
+
+pub fn wnear() -> AccountId {
+    "wnear.near".parse().unwrap()
+}
+
+pub fn usdt() -> AccountId {
+    "usdt.near".parse().unwrap()
+}
+
+/// (base, quote) of the book of every test order.
+pub fn wnear_usdt() -> PairId {
+    (wnear(), usdt())
+}
+
+/// A contract owned by `owner_id`, with the "wnear.near|usdt.near" pair.
+pub fn new_contract(owner_id: AccountId) -> Contract {
+    set_predecessor(owner_id);
+    let mut contract = Contract::new();
+    register_pair(&mut contract, "wnear.near", "usdt.near");
+    contract
+}
+
+pub fn set_predecessor(account_id: AccountId) {
+    testing_env!(VMContextBuilder::new()
+        .predecessor_account_id(account_id)
+        .build());
+}
+
+/// JSON of an order on the "wnear.near|usdt.near" book, wNEAR being the base token.
+pub fn order_json(account_id: &AccountId, side: &str, price: &str, amount: &str) -> String {
+    let (sell_token, buy_token) = match side {
+        "buy" => ("usdt.near", "wnear.near"),
+        _ => ("wnear.near", "usdt.near"),
+    };
+    format!(
+        "{{\"account_id\":\"{}\",\"amount\":\"{}\",\"sell_token\":\"{}\",\"buy_token\":\"{}\",\"price\":\"{}\",\"side\":\"{}\"}}",
+        account_id, amount, sell_token, buy_token, price, side
+    )
+}
+
+/// Registers the (base, quote) pair without fetching the token metadata, once.
+pub fn register_pair(contract: &mut Contract, base: &str, quote: &str) {
+    let pair_id: PairId = (base.parse().unwrap(), quote.parse().unwrap());
+    if contract.get_pair(pair_id.clone()).is_none() {
+        contract.internal_add_pair(&Pair {
+            base: pair_id.0,
+            quote: pair_id.1,
+            base_decimals: 24,
+            quote_decimals: 6,
+            tick_size: BigDecimal::from_str("0.01").unwrap(),
+            lot_size: BigDecimal::from_str("0.01").unwrap(),
+            min_notional: BigDecimal::from_str("1").unwrap(),
+            fees: None,
+            enabled: true,
+        });
+    }
+}
+
+/// Registers the account with a storage deposit big enough for any test, once.
+pub fn register(contract: &mut Contract, account_id: AccountId) {
+    if contract.storage_balance_of(account_id.clone()).is_none() {
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(account_id)
+            .attached_deposit(10 * ONE_NEAR)
+            .build());
+        contract.storage_deposit(None, None);
+    }
+}
+
+/// Deposits `amount` of `token_id` to the ledger the way a token contract does.
+pub fn deposit(contract: &mut Contract, account_id: AccountId, token_id: AccountId, amount: u128) {
+    register(contract, account_id.clone());
+    set_predecessor(token_id);
+    contract.ft_on_transfer(account_id, U128(amount), String::new());
+}
+
+/// Deposits enough usdt.near and wnear.near for every order of Alice, Bob and Carol.
+pub fn fund_accounts(contract: &mut Contract) {
+    use near_sdk::test_utils::test_env::{alice, bob, carol};
+
+    register_pair(contract, "wnear.near", "usdt.near");
+    for account_id in [alice(), bob(), carol()] {
+        deposit(
+            contract,
+            account_id.clone(),
+            "usdt.near".parse().unwrap(),
+            1_000_000,
+        );
+        deposit(
+            contract,
+            account_id,
+            "wnear.near".parse().unwrap(),
+            1_000_000,
+        );
+    }
+}
+
+/// Places the order after depositing exactly the tokens it locks.
+pub fn add_order(
+    contract: &mut Contract,
+    account_id: AccountId,
+    side: &str,
+    price: &str,
+    amount: &str,
+) -> U64 {
+    let order = order_json(&account_id, side, price, amount);
+    let parsed: Order = serde_json::from_str(&order).unwrap();
+    register_pair(contract, "wnear.near", "usdt.near");
+    register(contract, account_id.clone());
+    contract.internal_deposit(&account_id, &parsed.sell_token, parsed.sell_amount());
+
+    set_predecessor(account_id);
+    contract.add_order_from_string_3(order)
+}
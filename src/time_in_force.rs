@@ -0,0 +1,149 @@
+use crate::*;
+
+/// How long an order stays in the book.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum TimeInForce {
+    /// Good till cancelled: the remainder is placed in the book
+    #[default]
+    Gtc,
+    /// Immediate or cancel: the remainder is cancelled after matching
+    Ioc,
+    /// Fill or kill: the order is rejected unless it is filled completely
+    Fok,
+    /// The order is rejected if it would match at once, so it only adds liquidity
+    PostOnly,
+}
+
+impl Contract {
+    /// Rejects a fill-or-kill order the book can't fill completely
+    /// and a post-only order that would be filled at once.
+    /// Only reads the book, so a rejected order changes nothing.
+    pub(crate) fn check_time_in_force(&self, order: &Order) -> Result<(), OrderError> {
+        match order.time_in_force {
+            TimeInForce::Fok => {
+                let filled = self
+                    .plan_fills(order)
+                    .iter()
+                    .fold(BigDecimal::zero(), |sum, fill| sum + fill.amount);
+                if filled < order.amount {
+                    return Err(OrderError::NotFillable);
+                }
+            }
+            TimeInForce::PostOnly => {
+                if !self.plan_fills(order).is_empty() {
+                    return Err(OrderError::WouldTakeLiquidity);
+                }
+            }
+            TimeInForce::Gtc | TimeInForce::Ioc => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{
+        add_order, big, order_json, register, register_pair, set_predecessor, usdt, wnear,
+    };
+    use near_sdk::test_utils::test_env::{alice, bob, carol};
+
+    /// Asks of 10 at 2 and 5 at 3.
+    fn prepared_book() -> Contract {
+        let mut contract = Contract::new();
+        add_order(&mut contract, alice(), "sell", "2", "10");
+        add_order(&mut contract, carol(), "sell", "3", "5");
+        contract
+    }
+
+    /// Bob's buy order with the time in force, funded with the tokens it locks.
+    fn buy(
+        contract: &mut Contract,
+        price: &str,
+        amount: &str,
+        time_in_force: &str,
+    ) -> Result<u64, OrderError> {
+        let mut order: serde_json::Value =
+            serde_json::from_str(&order_json(&bob(), "buy", price, amount)).unwrap();
+        order["time_in_force"] = time_in_force.into();
+
+        register_pair(contract, "wnear.near", "usdt.near");
+        register(contract, bob());
+        contract.internal_deposit(&bob(), &usdt(), big(price) * big(amount));
+        set_predecessor(bob());
+        contract.add_order_from_string(order.to_string())
+    }
+
+    #[test]
+    fn test_gtc_rests() {
+        let mut contract = prepared_book();
+        let order_id = buy(&mut contract, "2", "12", "gtc").unwrap();
+
+        let order = contract.get_order(U64(order_id)).unwrap();
+        assert_eq!(order.status, OrderStatus::PartiallyFilled);
+        assert_eq!(order.amount, big("2"));
+    }
+
+    #[test]
+    fn test_ioc_cancels_the_remainder() {
+        let mut contract = prepared_book();
+        let order_id = buy(&mut contract, "2", "12", "ioc").unwrap();
+
+        assert!(contract.get_order(U64(order_id)).is_none());
+        let history = contract.view_order_history(bob(), None, None);
+        assert_eq!(history[0].1.status, OrderStatus::Cancelled);
+        assert_eq!(history[0].1.filled_amount, big("10"));
+        // The quote tokens of the remainder are unlocked
+        let balance = contract.get_balance(bob(), usdt());
+        assert_eq!(balance.available, big("4"));
+        assert_eq!(balance.locked, BigDecimal::zero());
+
+        let order_id = buy(&mut contract, "3", "5", "ioc").unwrap();
+        let history = contract.view_order_history(bob(), None, None);
+        assert_eq!(history[1].0, U64(order_id));
+        assert_eq!(history[1].1.status, OrderStatus::Filled);
+    }
+
+    #[test]
+    fn test_fok_is_rejected_unless_filled() {
+        let mut contract = prepared_book();
+        let nonce = contract.order_nonce;
+
+        assert_eq!(
+            buy(&mut contract, "2", "12", "fok"),
+            Err(OrderError::NotFillable)
+        );
+        assert_eq!(contract.order_nonce, nonce);
+        assert_eq!(contract.get_order(U64(1)).unwrap().amount, big("10"));
+        assert_eq!(contract.get_balance(bob(), usdt()).available, big("24"));
+
+        let order_id = buy(&mut contract, "3", "12", "fok").unwrap();
+        let history = contract.view_order_history(bob(), None, None);
+        assert_eq!(history[0].0, U64(order_id));
+        assert_eq!(history[0].1.status, OrderStatus::Filled);
+        assert_eq!(contract.get_order(U64(2)).unwrap().amount, big("3"));
+    }
+
+    #[test]
+    fn test_post_only_is_rejected_when_crossing() {
+        let mut contract = prepared_book();
+
+        assert_eq!(
+            buy(&mut contract, "2", "1", "post_only"),
+            Err(OrderError::WouldTakeLiquidity)
+        );
+        assert_eq!(contract.get_order(U64(1)).unwrap().amount, big("10"));
+
+        let order_id = buy(&mut contract, "1.99", "1", "post_only").unwrap();
+        assert_eq!(
+            contract.get_order(U64(order_id)).unwrap().status,
+            OrderStatus::Open
+        );
+        assert_eq!(
+            contract.view_order_book((wnear(), usdt()), 1).bids[0].price,
+            big("1.99")
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Trade {
+    pub maker_order_id: U64,
+    pub taker_order_id: U64,
+    pub maker_id: AccountId,
+    pub taker_id: AccountId,
+    /// (base, quote) of the book
+    pub pair_id: PairId,
+    pub taker_side: Side,
+    /// The maker's price
+    pub price: BigDecimal,
+    /// Amount of the base token
+    pub amount: BigDecimal,
+    /// Block timestamp in nanoseconds
+    pub timestamp: U64,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Trades where the account was the maker or the taker, the oldest first.
+    pub fn get_trades_by_account(
+        &self,
+        account_id: AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<Trade> {
+        match self.trades_by_account.get(&account_id) {
+            Some(trade_ids) => self.paginate_trades(&trade_ids, from_index, limit),
+            None => vec![],
+        }
+    }
+
+    /// Trades of the (base, quote) pair, the oldest first.
+    pub fn get_trades_by_pair(
+        &self,
+        pair_id: PairId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<Trade> {
+        match self.trades_by_pair.get(&pair_id) {
+            Some(trade_ids) => self.paginate_trades(&trade_ids, from_index, limit),
+            None => vec![],
+        }
+    }
+}
+
+impl Contract {
+    pub(crate) fn record_trade(&mut self, trade: Trade) {
+        let trade_id = self.trades.len();
+
+        self.push_account_trade(&trade.maker_id, trade_id);
+        if trade.taker_id != trade.maker_id {
+            self.push_account_trade(&trade.taker_id, trade_id);
+        }
+
+        let mut pair_trades = self.trades_by_pair.get(&trade.pair_id).unwrap_or_else(|| {
+            Vector::new(StorageKey::PairTrades {
+                pair_hash: env::sha256_array(&trade.pair_id.try_to_vec().unwrap()),
+            })
+        });
+        pair_trades.push(&trade_id);
+        self.trades_by_pair.insert(&trade.pair_id, &pair_trades);
+
+        self.trades.push(&trade);
+    }
+
+    fn push_account_trade(&mut self, account_id: &AccountId, trade_id: u64) {
+        let mut account_trades = self.trades_by_account.get(account_id).unwrap_or_else(|| {
+            Vector::new(StorageKey::AccountTrades {
+                account_hash: env::sha256_array(account_id.as_bytes()),
+            })
+        });
+        account_trades.push(&trade_id);
+        self.trades_by_account.insert(account_id, &account_trades);
+    }
+
+    fn paginate_trades(
+        &self,
+        trade_ids: &Vector<u64>,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<Trade> {
+        let from_index = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(DEFAULT_LIMIT);
+        (from_index..std::cmp::min(from_index.saturating_add(limit), trade_ids.len()))
+            .filter_map(|index| trade_ids.get(index))
+            .filter_map(|trade_id| self.trades.get(trade_id))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::add_order;
+    use near_sdk::test_utils::test_env::{alice, bob, carol};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_trades_are_recorded() {
+        let mut contract = Contract::new();
+        let pair_id: PairId = ("wnear.near".parse().unwrap(), "usdt.near".parse().unwrap());
+
+        let first = add_order(&mut contract, alice(), "sell", "2", "3");
+        let second = add_order(&mut contract, carol(), "sell", "2.1", "2");
+        let taker = add_order(&mut contract, bob(), "buy", "2.5", "4");
+
+        let trades = contract.get_trades_by_pair(pair_id.clone(), None, None);
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].maker_order_id, first);
+        assert_eq!(trades[0].taker_order_id, taker);
+        assert_eq!(trades[0].maker_id, alice());
+        assert_eq!(trades[0].taker_id, bob());
+        assert_eq!(trades[0].taker_side, Side::Buy);
+        assert_eq!(trades[0].amount, BigDecimal::from(3));
+        assert_eq!(trades[1].maker_order_id, second);
+        assert_eq!(trades[1].price, BigDecimal::from_str("2.1").unwrap());
+        assert_eq!(trades[1].amount, BigDecimal::from(1));
+
+        assert_eq!(contract.get_trades_by_account(bob(), None, None), trades);
+        assert_eq!(
+            contract.get_trades_by_account(alice(), None, None),
+            trades[..1]
+        );
+        assert_eq!(
+            contract.get_trades_by_account(carol(), None, None),
+            trades[1..]
+        );
+    }
+
+    #[test]
+    fn test_trades_pagination() {
+        let mut contract = Contract::new();
+        let pair_id: PairId = ("wnear.near".parse().unwrap(), "usdt.near".parse().unwrap());
+
+        for _ in 0..5 {
+            add_order(&mut contract, alice(), "sell", "2", "1");
+        }
+        add_order(&mut contract, bob(), "buy", "2", "5");
+
+        let page = contract.get_trades_by_pair(pair_id.clone(), Some(1), Some(2));
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[0].maker_order_id, U64(2));
+        assert_eq!(page[1].maker_order_id, U64(3));
+
+        assert_eq!(
+            contract
+                .get_trades_by_pair(pair_id.clone(), Some(4), Some(10))
+                .len(),
+            1
+        );
+        assert!(contract
+            .get_trades_by_pair(pair_id, Some(10), None)
+            .is_empty());
+        assert!(contract
+            .get_trades_by_account(carol(), None, None)
+            .is_empty());
+    }
+}
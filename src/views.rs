@@ -0,0 +1,240 @@
+use crate::*;
+
+/// Which orders a view returns. Every field left out matches all orders.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OrderFilter {
+    /// (base, quote) of the book
+    pub pair_id: Option<PairId>,
+    pub side: Option<Side>,
+    pub status: Option<OrderStatus>,
+    /// By id when omitted
+    pub sort_by: Option<OrderSort>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum OrderSort {
+    /// The oldest order first. The orders are read in this order
+    /// until the page is full, the later ones are not read.
+    #[default]
+    Id,
+    /// The lowest price first, then the oldest order.
+    /// Every order is read and sorted before the page is taken,
+    /// which costs gas with the number of orders of the list.
+    Price,
+}
+
+impl OrderFilter {
+    fn matches(&self, order: &Order) -> bool {
+        self.pair_id
+            .as_ref()
+            .is_none_or(|pair_id| &order.book_pair_id() == pair_id)
+            && self.side.is_none_or(|side| order.side == side)
+            && self.status.is_none_or(|status| order.status == status)
+    }
+
+    /// The page `[from_index, from_index + limit)` of the orders that match and are kept.
+    fn apply(
+        &self,
+        orders: &OrderList,
+        keep: impl Fn(&Order) -> bool,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<(U64, Order)> {
+        let matching = orders
+            .iter()
+            .filter(|(_, order)| keep(order) && self.matches(order));
+        match self.sort_by.unwrap_or_default() {
+            OrderSort::Id => page(matching, from_index, limit),
+            OrderSort::Price => {
+                let mut orders: Vec<(u64, Order)> = matching.collect();
+                orders.sort_by_key(|(order_id, order)| (order.price, *order_id));
+                page(orders.into_iter(), from_index, limit)
+            }
+        }
+    }
+}
+
+fn page(
+    orders: impl Iterator<Item = (u64, Order)>,
+    from_index: Option<u64>,
+    limit: Option<u64>,
+) -> Vec<(U64, Order)> {
+    orders
+        .skip(from_index.unwrap_or(0) as usize)
+        .take(limit.unwrap_or(DEFAULT_LIMIT) as usize)
+        .map(|(order_id, order)| (order_id.into(), order))
+        .collect()
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Open orders of the account, an empty list for an unknown account.
+    pub fn view_orders(
+        &self,
+        account_id: AccountId,
+        filter: Option<OrderFilter>,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<(U64, Order)> {
+        match self.orders.get(&account_id) {
+            Some(orders) => filter
+                .unwrap_or_default()
+                .apply(&orders, |_| true, from_index, limit),
+            None => vec![],
+        }
+    }
+
+    /// Orders selling `pair_id.0` for `pair_id.1`, an empty list for an unknown pair.
+    /// Both directions of a market share one entry of "ref_orders",
+    /// so the orders of the other direction are filtered out.
+    pub fn view_ref_orders(
+        &self,
+        pair_id: PairId,
+        filter: Option<OrderFilter>,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<(U64, Order)> {
+        let (sell_token, buy_token) = pair_id;
+        match self
+            .market_pair_id(&sell_token, &buy_token)
+            .and_then(|market_pair_id| self.ref_orders.get(&market_pair_id))
+        {
+            Some(orders) => filter.unwrap_or_default().apply(
+                &orders,
+                |order| order.sell_token == sell_token,
+                from_index,
+                limit,
+            ),
+            None => vec![],
+        }
+    }
+
+    /// Number of open orders of the account, read from the length of its map.
+    pub fn get_num_orders(&self, account_id: AccountId) -> u64 {
+        self.orders
+            .get(&account_id)
+            .map_or(0, |orders| orders.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{add_order, register_pair, set_predecessor, usdt, wnear};
+    use near_sdk::test_utils::test_env::{alice, bob};
+
+    fn ids(orders: Vec<(U64, Order)>) -> Vec<u64> {
+        orders.into_iter().map(|(order_id, _)| order_id.0).collect()
+    }
+
+    fn filter(side: Option<Side>, sort_by: Option<OrderSort>) -> Option<OrderFilter> {
+        Some(OrderFilter {
+            pair_id: None,
+            side,
+            status: None,
+            sort_by,
+        })
+    }
+
+    #[test]
+    fn test_view_orders_pages_and_filters() {
+        let mut contract = Contract::new();
+        add_order(&mut contract, alice(), "sell", "3", "1");
+        add_order(&mut contract, alice(), "buy", "1", "1");
+        add_order(&mut contract, alice(), "sell", "2", "1");
+        add_order(&mut contract, alice(), "buy", "1.5", "1");
+
+        assert_eq!(
+            ids(contract.view_orders(alice(), None, None, None)),
+            [1, 2, 3, 4]
+        );
+        assert_eq!(
+            ids(contract.view_orders(alice(), None, Some(1), Some(2))),
+            [2, 3]
+        );
+        assert!(contract
+            .view_orders(alice(), None, Some(4), None)
+            .is_empty());
+
+        let sells = contract.view_orders(alice(), filter(Some(Side::Sell), None), None, None);
+        assert_eq!(ids(sells), [1, 3]);
+        // The page counts the sells only
+        let sells = contract.view_orders(alice(), filter(Some(Side::Sell), None), Some(1), Some(2));
+        assert_eq!(ids(sells), [3]);
+        let by_price =
+            contract.view_orders(alice(), filter(None, Some(OrderSort::Price)), None, None);
+        assert_eq!(ids(by_price), [2, 4, 3, 1]);
+
+        let other_pair = Some(OrderFilter {
+            pair_id: Some((usdt(), wnear())),
+            ..Default::default()
+        });
+        assert!(contract
+            .view_orders(alice(), other_pair, None, None)
+            .is_empty());
+
+        assert_eq!(contract.get_num_orders(alice()), 4);
+        assert_eq!(contract.get_num_orders(bob()), 0);
+    }
+
+    #[test]
+    fn test_view_orders_after_a_cancel() {
+        let mut contract = Contract::new();
+        add_order(&mut contract, alice(), "sell", "3", "1");
+        add_order(&mut contract, alice(), "buy", "1", "1");
+        add_order(&mut contract, alice(), "sell", "2", "1");
+        set_predecessor(alice());
+        contract.cancel_order(U64(1));
+
+        // The orders stay sorted by id
+        assert_eq!(ids(contract.view_orders(alice(), None, None, None)), [2, 3]);
+        let by_price =
+            contract.view_orders(alice(), filter(None, Some(OrderSort::Price)), None, None);
+        assert_eq!(ids(by_price), [2, 3]);
+    }
+
+    #[test]
+    fn test_view_ref_orders_pages() {
+        let mut contract = Contract::new();
+        add_order(&mut contract, alice(), "sell", "3", "1");
+        add_order(&mut contract, bob(), "sell", "2", "1");
+        add_order(&mut contract, bob(), "buy", "1", "1");
+
+        let asks = contract.view_ref_orders(
+            (wnear(), usdt()),
+            filter(None, Some(OrderSort::Price)),
+            None,
+            Some(1),
+        );
+        assert_eq!(ids(asks), [2]);
+        assert_eq!(
+            ids(contract.view_ref_orders((usdt(), wnear()), None, None, None)),
+            [3]
+        );
+        // The orders of the other direction don't take the place of the page
+        assert_eq!(
+            ids(contract.view_ref_orders((usdt(), wnear()), None, None, Some(1))),
+            [3]
+        );
+    }
+
+    #[test]
+    fn test_views_of_unknown_keys() {
+        let mut contract = Contract::new();
+        assert!(contract.view_orders(alice(), None, None, None).is_empty());
+        assert!(contract
+            .view_ref_orders((wnear(), usdt()), None, None, None)
+            .is_empty());
+        assert_eq!(contract.get_num_pairs(), 0);
+
+        // A registered pair without orders
+        register_pair(&mut contract, "wnear.near", "usdt.near");
+        assert!(contract
+            .view_ref_orders((wnear(), usdt()), None, None, None)
+            .is_empty());
+        assert_eq!(contract.get_num_pairs(), 1);
+    }
+}